@@ -0,0 +1,212 @@
+use std::{error, fmt};
+
+use crate::{lexer::Token, parser::Expr};
+
+// every node starts with a tag telling what it is, a length follows where
+// the size isn't fixed. Lengths are u32 in little endian.
+const ATOM: u8 = 0;
+const CONS: u8 = 1;
+const SYMBOL: u8 = 0;
+const OP: u8 = 1;
+const STR: u8 = 2;
+const TRIVIA: u8 = 3;
+
+// MAX_DEPTH is how deep a decoded tree can be, so that a malicious input
+// can't overflow the stack
+const MAX_DEPTH: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    // input ends in the middle of a node
+    UnexpectedEnd,
+    InvalidTag(u8),
+    InvalidUtf8,
+    // bytes are left after the root node
+    TrailingBytes,
+    // nodes are nested deeper than MAX_DEPTH
+    TooDeep,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag: {}", tag),
+            DecodeError::InvalidUtf8 => write!(f, "invalid utf-8 string"),
+            DecodeError::TrailingBytes => write!(f, "trailing bytes after expression"),
+            DecodeError::TooDeep => write!(f, "expression nested too deep"),
+        }
+    }
+}
+
+impl error::Error for DecodeError {}
+
+impl Expr {
+    // to_bytes encode the tree in a tag-length-value format, from_bytes
+    // decode it back to the same tree
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        encode(self, &mut bytes);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Expr, DecodeError> {
+        let mut decoder = Decoder { bytes, depth: 0 };
+        let expr = decoder.expr()?;
+        if !decoder.bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(expr)
+    }
+}
+
+fn encode(expr: &Expr, bytes: &mut Vec<u8>) {
+    match expr {
+        Expr::Atom(token) => {
+            bytes.push(ATOM);
+            encode_token(token, bytes);
+        }
+        Expr::Cons(head, rest) => {
+            bytes.push(CONS);
+            encode_token(head, bytes);
+            bytes.extend((rest.len() as u32).to_le_bytes());
+            for child in rest {
+                encode(child, bytes);
+            }
+        }
+    }
+}
+
+fn encode_token(token: &Token, bytes: &mut Vec<u8>) {
+    let (tag, s) = match token {
+        Token::Symbol(s) => (SYMBOL, s),
+        Token::Op(s) => (OP, s),
+        Token::Str(s) => (STR, s),
+        Token::Trivia(s) => (TRIVIA, s),
+    };
+    bytes.push(tag);
+    bytes.extend((s.len() as u32).to_le_bytes());
+    bytes.extend(s.as_bytes());
+}
+
+// Decoder read nodes from the front of bytes
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    // nodes being decoded from the root to the current one
+    depth: usize,
+}
+
+impl Decoder<'_> {
+    fn expr(&mut self) -> Result<Expr, DecodeError> {
+        if self.depth == MAX_DEPTH {
+            return Err(DecodeError::TooDeep);
+        }
+        self.depth += 1;
+        let expr = self.node();
+        self.depth -= 1;
+        expr
+    }
+
+    fn node(&mut self) -> Result<Expr, DecodeError> {
+        match self.take(1)?[0] {
+            ATOM => Ok(Expr::Atom(self.token()?)),
+            CONS => {
+                let head = self.token()?;
+                let len = self.len()?;
+                // don't trust len to reserve memory, the input may be short
+                let mut rest = vec![];
+                for _ in 0..len {
+                    rest.push(self.expr()?);
+                }
+                Ok(Expr::Cons(head, rest))
+            }
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, DecodeError> {
+        let tag = self.take(1)?[0];
+        let len = self.len()?;
+        let s = std::str::from_utf8(self.take(len)?)
+            .map_err(|_| DecodeError::InvalidUtf8)?
+            .to_string();
+        match tag {
+            SYMBOL => Ok(Token::Symbol(s)),
+            OP => Ok(Token::Op(s)),
+            STR => Ok(Token::Str(s)),
+            TRIVIA => Ok(Token::Trivia(s)),
+            _ => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+    }
+
+    fn take(&mut self, n: usize) -> Result<&[u8], DecodeError> {
+        if self.bytes.len() < n {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_bytes() {
+        for input in ["1 + 2 * 3", "max(x, -1) - f()", r#""a b" + |√2|"#] {
+            let s = parse(input).unwrap();
+            let decoded = Expr::from_bytes(&s.to_bytes()).unwrap();
+            assert_eq!(decoded, s);
+            assert_eq!(decoded.to_string(), s.to_string());
+        }
+        let s = parse("1 + 2 * 3").unwrap();
+        assert_eq!(
+            Expr::from_bytes(&s.to_bytes()).unwrap().to_string(),
+            "(+ 1 (* 2 3))"
+        );
+    }
+
+    #[test]
+    fn test_decode_error() {
+        let bytes = parse("1 + 2 * 3").unwrap().to_bytes();
+        for end in 0..bytes.len() {
+            assert_eq!(
+                Expr::from_bytes(&bytes[..end]),
+                Err(DecodeError::UnexpectedEnd)
+            );
+        }
+        let mut trailing = bytes.clone();
+        trailing.push(ATOM);
+        assert_eq!(Expr::from_bytes(&trailing), Err(DecodeError::TrailingBytes));
+        assert_eq!(Expr::from_bytes(&[7]), Err(DecodeError::InvalidTag(7)));
+        assert_eq!(
+            Expr::from_bytes(&[ATOM, SYMBOL, 1, 0, 0, 0, 0xff]),
+            Err(DecodeError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let nested = |depth| {
+            (1..depth).fold(parse("1").unwrap(), |expr, _| {
+                Expr::Cons(Token::Op("-".to_string()), vec![expr])
+            })
+        };
+        let s = nested(MAX_DEPTH);
+        assert_eq!(Expr::from_bytes(&s.to_bytes()), Ok(s));
+        let s = nested(MAX_DEPTH + 1);
+        assert_eq!(Expr::from_bytes(&s.to_bytes()), Err(DecodeError::TooDeep));
+
+        // the depth is checked before the input runs out
+        let bytes = [CONS, OP, 1, 0, 0, 0, b'-', 1, 0, 0, 0].repeat(100_000);
+        assert_eq!(Expr::from_bytes(&bytes), Err(DecodeError::TooDeep));
+    }
+}
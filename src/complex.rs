@@ -0,0 +1,111 @@
+use num_complex::Complex64;
+
+use crate::{
+    eval::{parse_number, EvalError},
+    lexer::{is_number, Token},
+    parser::Expr,
+};
+
+pub type Complex = Complex64;
+
+// IMAGINARY are the names of the imaginary unit, a number right before one
+// like "2i" is imaginary
+const IMAGINARY: &[&str] = &["i", "j"];
+
+// eval_complex is eval but compute with complex numbers, "i" and "j" are the
+// imaginary unit
+pub fn eval_complex(expr: &Expr) -> Result<Complex, EvalError> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) => parse_complex(s),
+        Expr::Cons(Token::Symbol(name), args) => match (name.as_str(), args.as_slice()) {
+            ("abs", [x]) => Ok(eval_complex(x)?.norm().into()),
+            ("conj", [x]) => Ok(eval_complex(x)?.conj()),
+            _ => Err(EvalError::UnknownFunction(name.clone())),
+        },
+        Expr::Cons(Token::Op(op), args) => {
+            let args = args
+                .iter()
+                .map(eval_complex)
+                .collect::<Result<Vec<_>, _>>()?;
+            match (op.as_str(), args.as_slice()) {
+                ("+", [a]) => Ok(*a),
+                ("-", [a]) => Ok(-a),
+                ("+", [a, b]) => Ok(a + b),
+                ("-", [a, b]) => Ok(a - b),
+                ("*", [a, b]) => Ok(a * b),
+                ("/", [_, b]) if *b == Complex64::default() => Err(EvalError::DivisionByZero),
+                ("/", [a, b]) => Ok(a / b),
+                // "0 ^ -1" is "1 / 0"
+                ("^", [a, b]) if *a == Complex64::default() && b.re < 0.0 => {
+                    Err(EvalError::DivisionByZero)
+                }
+                // an integer power is exact like "i^2", powc goes through
+                // logarithms. A power past i32 would saturate.
+                ("^", [a, b])
+                    if b.im == 0.0 && b.re.fract() == 0.0 && b.re.abs() <= i32::MAX as f64 =>
+                {
+                    Ok(a.powi(b.re as i32))
+                }
+                ("^", [a, b]) => Ok(a.powc(*b)),
+                _ => Err(EvalError::UnknownOperator(op.clone())),
+            }
+        }
+        Expr::Atom(token) | Expr::Cons(token, _) => {
+            Err(EvalError::UnknownOperator(token.to_string()))
+        }
+    }
+}
+
+fn parse_complex(s: &str) -> Result<Complex64, EvalError> {
+    if IMAGINARY.contains(&s) {
+        return Ok(Complex64::i());
+    }
+    if !is_number(s) {
+        return Err(EvalError::UndefinedVariable(s.to_string()));
+    }
+    let (digits, unit) = match IMAGINARY.iter().find_map(|unit| s.strip_suffix(unit)) {
+        Some(digits) => (digits, Complex64::i()),
+        None => (s, Complex64::new(1.0, 0.0)),
+    };
+    let value = parse_number(digits).ok_or_else(|| EvalError::InvalidNumber(s.to_string()))?;
+    Ok(unit * value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn eval_str(input: &str) -> Result<Complex64, EvalError> {
+        eval_complex(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_eval_complex() {
+        assert_eq!(eval_str("(1 + 2i) * (3 - i)"), Ok(Complex64::new(5.0, 5.0)));
+        assert_eq!(eval_str("i * i"), Ok(Complex64::new(-1.0, 0.0)));
+        assert_eq!(eval_str("j^2 + 1"), Ok(Complex64::new(0.0, 0.0)));
+        assert_eq!(eval_str("2.5j / 0.5"), Ok(Complex64::new(0.0, 5.0)));
+        assert_eq!(eval_str("abs(3 + 4i)"), Ok(Complex64::new(5.0, 0.0)));
+        assert_eq!(eval_str("conj(1 + i)"), Ok(Complex64::new(1.0, -1.0)));
+        assert_eq!(eval_str("1 / (i - i)"), Err(EvalError::DivisionByZero));
+        assert_eq!(eval_str("i ^ -3"), Ok(Complex64::new(0.0, 1.0)));
+        assert_eq!(eval_str("0 ^ -1"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            eval_str("(i - i) ^ (-0.5 + i)"),
+            Err(EvalError::DivisionByZero)
+        );
+        assert_eq!(eval_str("0 ^ 2"), Ok(Complex64::new(0.0, 0.0)));
+        let big = eval_str("i ^ 3000000000").unwrap();
+        assert!((big - Complex64::new(1.0, 0.0)).norm() < 1e-3, "{}", big);
+        assert_eq!(eval_str("2k"), Ok(Complex64::new(2000.0, 0.0)));
+        assert_eq!(
+            eval_str("2x"),
+            Err(EvalError::InvalidNumber("2x".to_string()))
+        );
+        assert_eq!(
+            eval_str("x + i"),
+            Err(EvalError::UndefinedVariable("x".to_string()))
+        );
+    }
+}
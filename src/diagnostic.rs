@@ -0,0 +1,127 @@
+use crate::{
+    lexer::{tokenize_spanned, LexOptions, Span},
+    parser::{parse, ParseError},
+};
+
+// Diagnostic is a recoverable problem found while parsing, the input is still
+// parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+// render_error format err like rustc's diagnostics, the line of input where
+// the error is found is reproduced with its span underlined by "^". Errors
+// without a span point at the end of input.
+pub fn render_error(input: &str, err: &ParseError) -> String {
+    let span = err.span().unwrap_or(input.len()..input.len());
+    let line_start = input[..span.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = input[span.start..]
+        .find('\n')
+        .map_or(input.len(), |i| span.start + i);
+    let line = &input[line_start..line_end];
+    let line_no = input[..line_start].matches('\n').count() + 1;
+
+    // columns are counted in chars so that carets line up with "√" and
+    // friends, a span crossing the line end is cut there.
+    let column = input[line_start..span.start].chars().count();
+    let width = input[span.start..span.end.min(line_end)].chars().count();
+    let gutter = " ".repeat(line_no.to_string().len());
+    format!(
+        "error: {}\n{gutter}--> {}:{}\n{gutter} |\n{} | {}\n{gutter} | {}{}",
+        err,
+        line_no,
+        column + 1,
+        line_no,
+        line,
+        " ".repeat(column),
+        "^".repeat(width.max(1)),
+    )
+}
+
+// redundant_parens warn about every group whose parens don't change the
+// parse, like both groups of "((1))" or the one of "(1 + 2) + 3". A group is
+// redundant if the input still parses to the same tree without its parens.
+pub fn redundant_parens(input: &str) -> Result<Vec<Diagnostic>, ParseError> {
+    let expr = parse(input)?;
+    let tokens = tokenize_spanned(input, &LexOptions::default(), &[])?;
+    // spans of open groups, parens of a call are None
+    let mut opened = vec![];
+    let mut diagnostics = vec![];
+    let mut after_name = false;
+    for (token, span) in tokens {
+        if token.is_op("(") && after_name {
+            opened.push(None);
+        } else if token.is_op("(") || token.is_op("[") {
+            opened.push(Some(span));
+        } else if token.is_op(")") || token.is_op("]") {
+            if let Some(Some(open)) = opened.pop() {
+                // blank out both parens, so every other token stays in place
+                let mut ungrouped = input.to_string();
+                ungrouped.replace_range(span.clone(), &" ".repeat(span.len()));
+                ungrouped.replace_range(open.clone(), &" ".repeat(open.len()));
+                if parse(&ungrouped).as_ref() == Ok(&expr) {
+                    diagnostics.push(Diagnostic {
+                        message: "redundant parentheses".to_string(),
+                        span: open.start..span.end,
+                    });
+                }
+            }
+        }
+        after_name = token.is_name();
+    }
+    // outer groups first
+    diagnostics.sort_by_key(|d| d.span.start);
+    Ok(diagnostics)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    #[test]
+    fn test_redundant_parens() {
+        let spans = |input| {
+            let diagnostics = redundant_parens(input).unwrap();
+            let spans = diagnostics.into_iter().map(|d| (d.span.start, d.span.end));
+            spans.collect::<Vec<_>>()
+        };
+        assert_eq!(spans("((1))"), [(0, 5), (1, 4)]);
+        assert_eq!(spans("(1)"), [(0, 3)]);
+        assert_eq!(spans("(1 + 2) + 3"), [(0, 7)]);
+        assert_eq!(spans("[2 * 3] + 1"), [(0, 7)]);
+        assert_eq!(spans("(1 + 2) * 3"), []);
+        assert_eq!(spans("1 - (2 - 3)"), []);
+        // call parens are never redundant
+        assert_eq!(spans("f(x) * (g((y)))"), [(7, 15), (10, 13)]);
+        assert!(redundant_parens("(1").is_err());
+    }
+
+    #[test]
+    fn test_render_error() {
+        let input = "1 + * 2";
+        let rendered = render_error(input, &parse(input).unwrap_err());
+        assert_eq!(
+            rendered,
+            "error: expected an operand but found '*'\n \
+             --> 1:5\n  |\n\
+             1 | 1 + * 2\n  |     ^"
+        );
+
+        // the caret is under the "*" of the reproduced line
+        let lines: Vec<&str> = rendered.lines().collect();
+        let star = lines[3].find('*').unwrap();
+        assert_eq!(lines[4].find('^'), Some(star));
+
+        // only the line with the error is reproduced
+        let input = "1 +\n(3 ]";
+        let rendered = render_error(input, &parse(input).unwrap_err());
+        assert!(rendered.ends_with("--> 2:4\n  |\n2 | (3 ]\n  |    ^"));
+
+        let input = "√9 +";
+        let rendered = render_error(input, &parse(input).unwrap_err());
+        assert!(rendered.ends_with("1 | √9 +\n  |     ^"));
+    }
+}
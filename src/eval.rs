@@ -0,0 +1,963 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    error,
+    f64::consts::{E, PI},
+    fmt,
+};
+
+use crate::{
+    lexer::{is_number, Token},
+    parser::{DisplayStyle, Expr, Where, COMPARISONS},
+    transform::rename_var,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    InvalidNumber(String),
+    UndefinedVariable(String),
+    UnknownOperator(String),
+    UnknownFunction(String),
+    // a function is called with a wrong number of arguments
+    WrongArgumentCount(String),
+    UnknownUnit(String),
+    // operands of an operator have units that can't be combined, like "3km + 5s"
+    IncompatibleUnits(String),
+    // an operator can't be applied to the value of an operand, like a
+    // negative exponent in integer arithmetic
+    InvalidOperand(String),
+    // eval found a value that isn't a number, like the result of "1 < 2"
+    NotANumber(String),
+    Overflow,
+    DivisionByZero,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::InvalidNumber(s) => write!(f, "invalid number: {}", s),
+            EvalError::UndefinedVariable(s) => write!(f, "undefined variable: {}", s),
+            EvalError::UnknownOperator(op) => write!(f, "unknown operator: {}", op),
+            EvalError::UnknownFunction(name) => write!(f, "unknown function: {}", name),
+            EvalError::WrongArgumentCount(name) => {
+                write!(f, "wrong number of arguments: {}", name)
+            }
+            EvalError::UnknownUnit(unit) => write!(f, "unknown unit: {}", unit),
+            EvalError::IncompatibleUnits(op) => write!(f, "incompatible units for: {}", op),
+            EvalError::InvalidOperand(op) => write!(f, "invalid operand for: {}", op),
+            EvalError::NotANumber(s) => write!(f, "not a number: {}", s),
+            EvalError::Overflow => write!(f, "overflow"),
+            EvalError::DivisionByZero => write!(f, "division by zero"),
+        }
+    }
+}
+
+impl error::Error for EvalError {}
+
+// Value is what eval_value compute, a number or a boolean
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+type Function = Box<dyn Fn(&[f64]) -> Result<f64, EvalError>>;
+
+// Functions are functions eval_with can call besides the builtin ones, a
+// registered function hides the builtin one of the same name
+#[derive(Default)]
+pub struct Functions {
+    functions: HashMap<String, Function>,
+}
+
+impl Functions {
+    pub fn new() -> Functions {
+        Functions::default()
+    }
+
+    pub fn register(
+        &mut self,
+        name: &str,
+        function: impl Fn(&[f64]) -> Result<f64, EvalError> + 'static,
+    ) {
+        self.functions.insert(name.to_string(), Box::new(function));
+    }
+
+    fn call(&self, name: &str, args: &[f64]) -> Result<f64, EvalError> {
+        match self.functions.get(name) {
+            Some(function) => function(args),
+            None => call(name, args),
+        }
+    }
+}
+
+// DivisionMode is how "/" divides two integers, other numbers are always
+// divided as floats
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DivisionMode {
+    #[default]
+    Float,
+    // round the quotient toward negative infinity, "-7 / 2" is -4
+    IntegerFloor,
+    // round the quotient toward zero, "-7 / 2" is -3
+    IntegerTrunc,
+}
+
+// Env is what evaluating depends on besides the expression, values of nodes
+// are kept in memo if there is one
+#[derive(Clone, Copy)]
+struct Env<'a> {
+    functions: &'a Functions,
+    division: DivisionMode,
+    memo: Option<&'a RefCell<HashMap<&'a Expr, Value>>>,
+}
+
+impl<'a> Env<'a> {
+    fn new(functions: &'a Functions) -> Env<'a> {
+        Env {
+            functions,
+            division: DivisionMode::Float,
+            memo: None,
+        }
+    }
+}
+
+// eval compute the value of an Expr, a Symbol atom is a number, a Cons with a
+// Symbol head is a function call. The value must be a number.
+pub fn eval(expr: &Expr) -> Result<f64, EvalError> {
+    eval_with(expr, &Functions::new())
+}
+
+// eval_with is eval but functions can also be the ones of functions
+pub fn eval_with(expr: &Expr, functions: &Functions) -> Result<f64, EvalError> {
+    number_of(expr, Env::new(functions))
+}
+
+// eval_with_division is eval but "/" divides integers as division tells
+pub fn eval_with_division(expr: &Expr, division: DivisionMode) -> Result<f64, EvalError> {
+    let functions = &Functions::new();
+    number_of(
+        expr,
+        Env {
+            division,
+            ..Env::new(functions)
+        },
+    )
+}
+
+// eval_memoized is eval_with but a subtree repeated like the "f(x)" of
+// "f(x) * f(x)" is only computed once, functions must be pure
+pub fn eval_memoized(expr: &Expr, functions: &Functions) -> Result<f64, EvalError> {
+    let memo = RefCell::new(HashMap::new());
+    number_of(
+        expr,
+        Env {
+            memo: Some(&memo),
+            ..Env::new(functions)
+        },
+    )
+}
+
+fn number_of<'a>(expr: &'a Expr, env: Env<'a>) -> Result<f64, EvalError> {
+    match value_of(expr, env)? {
+        Value::Number(n) => Ok(n),
+        Value::Bool(_) => Err(EvalError::NotANumber(expr.to_string())),
+    }
+}
+
+// eval_value is eval but the value can be a boolean, "true" and "false" are
+// keywords so they are never variables. "&&" only evaluates its right operand
+// if the left one is true, "??" only evaluates its right operand if the left
+// one has an undefined variable.
+pub fn eval_value(expr: &Expr) -> Result<Value, EvalError> {
+    value_of(expr, Env::new(&Functions::new()))
+}
+
+fn value_of<'a>(expr: &'a Expr, env: Env<'a>) -> Result<Value, EvalError> {
+    let (Some(memo), Expr::Cons(..)) = (env.memo, expr) else {
+        return compute(expr, env);
+    };
+    if let Some(value) = memo.borrow().get(expr) {
+        return Ok(*value);
+    }
+    let value = compute(expr, env)?;
+    memo.borrow_mut().insert(expr, value);
+    Ok(value)
+}
+
+fn compute<'a>(expr: &'a Expr, env: Env<'a>) -> Result<Value, EvalError> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) => match as_bool(s) {
+            Some(b) => Ok(Value::Bool(b)),
+            None => eval_atom(s).map(Value::Number),
+        },
+        Expr::Cons(Token::Symbol(name), args) => {
+            let args = args
+                .iter()
+                .map(|arg| number_of(arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            env.functions.call(name, &args).map(Value::Number)
+        }
+        Expr::Cons(Token::Op(op), args) if op == "&&" => {
+            eval_and(args, &mut |arg| value_of(arg, env))
+        }
+        Expr::Cons(Token::Op(op), args) if op == "??" && args.len() == 2 => {
+            match value_of(&args[0], env) {
+                Err(EvalError::UndefinedVariable(_)) => value_of(&args[1], env),
+                value => value,
+            }
+        }
+        Expr::Cons(Token::Op(op), args) => {
+            let args = args
+                .iter()
+                .map(|arg| value_of(arg, env))
+                .collect::<Result<Vec<_>, _>>()?;
+            match (op.as_str(), args.as_slice()) {
+                ("/", [Value::Number(a), Value::Number(b)]) => {
+                    divide(*a, *b, env.division).map(Value::Number)
+                }
+                _ => apply_value(op, &args),
+            }
+        }
+        Expr::Atom(token) | Expr::Cons(token, _) => {
+            Err(EvalError::UnknownOperator(token.to_string()))
+        }
+    }
+}
+
+// eval_and evaluate operands of "&&" with eval_operand until one is false. In
+// a comparison chain like "(&& (< 1 x) (< x 10))" a comparison sharing the
+// right operand of the previous one reuses its value, so the shared x is only
+// evaluated once.
+fn eval_and<'a>(
+    args: &'a [Expr],
+    eval_operand: &mut impl FnMut(&'a Expr) -> Result<Value, EvalError>,
+) -> Result<Value, EvalError> {
+    let mut shared: Option<(&Expr, Value)> = None;
+    for arg in args {
+        let value = match arg {
+            Expr::Cons(Token::Op(op), operands)
+                if COMPARISONS.contains(&op.as_str()) && operands.len() == 2 =>
+            {
+                let left = match shared {
+                    Some((expr, value)) if *expr == operands[0] => value,
+                    _ => eval_operand(&operands[0])?,
+                };
+                let right = eval_operand(&operands[1])?;
+                shared = Some((&operands[1], right));
+                apply_value(op, &[left, right])?
+            }
+            _ => {
+                shared = None;
+                eval_operand(arg)?
+            }
+        };
+        match value {
+            Value::Bool(true) => {}
+            Value::Bool(false) => return Ok(Value::Bool(false)),
+            Value::Number(_) => return Err(EvalError::InvalidOperand("&&".to_string())),
+        }
+    }
+    Ok(Value::Bool(true))
+}
+
+fn divide(a: f64, b: f64, division: DivisionMode) -> Result<f64, EvalError> {
+    let integers = a.fract() == 0.0 && b.fract() == 0.0;
+    match division {
+        _ if b == 0.0 => Err(EvalError::DivisionByZero),
+        DivisionMode::IntegerFloor if integers => Ok((a / b).floor()),
+        DivisionMode::IntegerTrunc if integers => Ok((a / b).trunc()),
+        _ => Ok(a / b),
+    }
+}
+
+pub(crate) fn as_bool(s: &str) -> Option<bool> {
+    match s {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+// apply_value compare values, any other operator is applied to numbers
+fn apply_value(op: &str, args: &[Value]) -> Result<Value, EvalError> {
+    let compared = match (op, args) {
+        ("==", [a, b]) => a == b,
+        ("!=", [a, b]) => a != b,
+        ("<", [Value::Number(a), Value::Number(b)]) => a < b,
+        (">", [Value::Number(a), Value::Number(b)]) => a > b,
+        ("<=", [Value::Number(a), Value::Number(b)]) => a <= b,
+        (">=", [Value::Number(a), Value::Number(b)]) => a >= b,
+        _ => {
+            let numbers = args
+                .iter()
+                .map(|arg| match arg {
+                    Value::Number(n) => Ok(*n),
+                    Value::Bool(_) => Err(EvalError::InvalidOperand(op.to_string())),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            return apply(op, &numbers).map(Value::Number);
+        }
+    };
+    Ok(Value::Bool(compared))
+}
+
+// CONSTANTS are names eval knows the value of
+const CONSTANTS: &[(&str, f64)] = &[("pi", PI), ("e", E)];
+
+// FUNCTIONS are the builtin functions of eval, all of them are pure
+const FUNCTIONS: &[&str] = &[
+    "abs", "sqrt", "exp", "ln", "log", "sin", "cos", "tan", "min", "max",
+];
+
+// SI_PREFIXES are the magnitude suffixes a number can have, they are pure
+// scalars so "3k" is 3000
+const SI_PREFIXES: &[(char, f64)] = &[('k', 1e3), ('M', 1e6), ('G', 1e9)];
+
+// parse_number parse a number literal, with an optional SI prefix right
+// after its digits. A unit like the "km" of "3km" is not a prefix.
+pub(crate) fn parse_number(s: &str) -> Option<f64> {
+    if let Ok(value) = s.parse() {
+        return Some(value);
+    }
+    SI_PREFIXES.iter().find_map(|(prefix, scale)| {
+        let value = s.strip_suffix(*prefix)?.parse::<f64>().ok()?;
+        Some(value * scale)
+    })
+}
+
+fn eval_atom(s: &str) -> Result<f64, EvalError> {
+    if is_number(s) {
+        return parse_number(s).ok_or_else(|| EvalError::InvalidNumber(s.to_string()));
+    }
+    match CONSTANTS.iter().find(|(name, _)| *name == s) {
+        Some((_, value)) => Ok(*value),
+        // a name made of non ASCII digits like "١٢٣" isn't read as a number
+        None if s.starts_with(char::is_numeric) => Err(EvalError::InvalidNumber(s.to_string())),
+        None => Err(EvalError::UndefinedVariable(s.to_string())),
+    }
+}
+
+fn apply(op: &str, args: &[f64]) -> Result<f64, EvalError> {
+    match (op, args) {
+        ("+", [a]) => Ok(*a),
+        ("-", [a]) => Ok(-a),
+        ("√", [a]) => Ok(a.sqrt()),
+        ("deg", [a]) => Ok(a.to_radians()),
+        ("rad", [a]) => Ok(*a),
+        // a number is always defined
+        ("??", [a, _]) => Ok(*a),
+        ("+", [a, b]) => Ok(a + b),
+        ("-", [a, b]) => Ok(a - b),
+        ("*", [a, b]) => Ok(a * b),
+        ("/", [_, b]) if *b == 0.0 => Err(EvalError::DivisionByZero),
+        ("/", [a, b]) => Ok(a / b),
+        ("^", [a, b]) => Ok(a.powf(*b)),
+        // chains merged by flatten
+        ("+", [_, _, ..]) => Ok(args.iter().sum()),
+        ("*", [_, _, ..]) => Ok(args.iter().product()),
+        _ => Err(EvalError::UnknownOperator(op.to_string())),
+    }
+}
+
+pub(crate) fn call(name: &str, args: &[f64]) -> Result<f64, EvalError> {
+    let value = match (name, args) {
+        ("abs", [x]) => x.abs(),
+        ("sqrt", [x]) => x.sqrt(),
+        ("exp", [x]) => x.exp(),
+        ("ln", [x]) => x.ln(),
+        ("log", [x]) => x.log10(),
+        ("sin", [x]) => x.sin(),
+        ("cos", [x]) => x.cos(),
+        ("tan", [x]) => x.tan(),
+        ("min", [a, b]) => a.min(*b),
+        ("max", [a, b]) => a.max(*b),
+        _ if FUNCTIONS.contains(&name) => {
+            return Err(EvalError::WrongArgumentCount(name.to_string()))
+        }
+        _ => return Err(EvalError::UnknownFunction(name.to_string())),
+    };
+    Ok(value)
+}
+
+impl Expr {
+    // is_constant check if the expression only has numbers, booleans, known
+    // constants and builtin functions applied to them, so it always evaluates to the
+    // same value.
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Expr::Atom(Token::Symbol(s)) => {
+                is_number(s) || as_bool(s).is_some() || CONSTANTS.iter().any(|(name, _)| name == s)
+            }
+            Expr::Cons(Token::Symbol(name), args) => {
+                FUNCTIONS.contains(&name.as_str()) && args.iter().all(Expr::is_constant)
+            }
+            Expr::Cons(Token::Op(_), args) => args.iter().all(Expr::is_constant),
+            Expr::Atom(_) | Expr::Cons(Token::Str(_) | Token::Trivia(_), _) => false,
+        }
+    }
+}
+
+// partial_eval substitute variables with known values and compute every
+// operator whose operands are all numbers, what can't be computed yet is left
+// as is. partial_eval(parse("x * y + 1"), {x: 2}) is "(+ (* 2 y) 1)".
+pub fn partial_eval(expr: &Expr, vars: &HashMap<String, f64>) -> Expr {
+    fold(expr, vars, false)
+}
+
+// eval_numeric compute every subtree having only numbers, function calls
+// included, anything touching a variable is left symbolic.
+// eval_numeric(parse("sqrt(4) + x")) is "(+ 2 x)".
+pub fn eval_numeric(expr: &Expr) -> Expr {
+    fold(expr, &HashMap::new(), true)
+}
+
+// fold is partial_eval which also applies function calls if calls is set
+fn fold(expr: &Expr, vars: &HashMap<String, f64>, calls: bool) -> Expr {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) if !is_number(s) && as_bool(s).is_none() => {
+            match vars.get(s) {
+                Some(value) => number(*value),
+                None => expr.clone(),
+            }
+        }
+        Expr::Atom(_) => expr.clone(),
+        Expr::Cons(head, args) => {
+            let args = args
+                .iter()
+                .map(|arg| fold(arg, vars, calls))
+                .collect::<Vec<_>>();
+            let values = args.iter().map(as_number).collect::<Option<Vec<_>>>();
+            // an error like a division by zero is left to eval
+            let value = match (head, values) {
+                (Token::Op(op), Some(values)) => apply(op, &values).ok(),
+                (Token::Symbol(name), Some(values)) if calls => call(name, &values).ok(),
+                _ => None,
+            };
+            match value {
+                Some(value) if value.is_finite() => number(value),
+                _ => Expr::Cons(head.clone(), args),
+            }
+        }
+    }
+}
+
+// eval_where compute the expression with the variables of its bindings, a
+// binding can use the variables of the other ones in any order. A binding
+// using itself, directly or not, is an undefined variable. Like eval_value a
+// binding or the result can be a boolean.
+pub fn eval_where(expr: &Where) -> Result<Value, EvalError> {
+    let mut vars = HashMap::new();
+    let mut pending = expr.bindings.iter().collect::<Vec<_>>();
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut error = None;
+        pending.retain(|(name, value)| match eval_value(&bind_vars(value, &vars)) {
+            Ok(value) => {
+                vars.insert(name.clone(), value);
+                false
+            }
+            Err(err) => {
+                error.get_or_insert(err);
+                true
+            }
+        });
+        // no binding could be computed in this round
+        if let (true, Some(err)) = (pending.len() == before, error) {
+            return Err(err);
+        }
+    }
+    eval_value(&bind_vars(&expr.expr, &vars))
+}
+
+// bind_vars substitute variables with their values, a boolean one by "true"
+// or "false"
+fn bind_vars(expr: &Expr, vars: &HashMap<String, Value>) -> Expr {
+    let mut numbers = HashMap::new();
+    let mut expr = expr.clone();
+    for (name, value) in vars {
+        match value {
+            Value::Number(n) => {
+                numbers.insert(name.clone(), *n);
+            }
+            Value::Bool(b) => expr = rename_var(expr, name, &b.to_string()),
+        }
+    }
+    partial_eval(&expr, &numbers)
+}
+
+// evaluation_steps list every operation in the order eval computes them with
+// its value, "1 + 2 * 3" is "2 * 3 = 6" then "1 + 6 = 7". An operation on a
+// variable or failing like "1 / 0" can't be computed, it's listed with the
+// values known so far like "x + 6".
+pub fn evaluation_steps(expr: &Expr) -> Vec<String> {
+    let mut steps = vec![];
+    fold_steps(expr, &mut steps);
+    steps
+}
+
+fn fold_steps(expr: &Expr, steps: &mut Vec<String>) -> Expr {
+    let Expr::Cons(head, args) = expr else {
+        return expr.clone();
+    };
+    // a negative literal is a value, not a step
+    if as_number(expr).is_some() {
+        return expr.clone();
+    }
+    let args = args
+        .iter()
+        .map(|arg| fold_steps(arg, steps))
+        .collect::<Vec<_>>();
+    let values = args.iter().map(as_value).collect::<Option<Vec<_>>>();
+    let value = match (head, values) {
+        // like eval, "false && x" is false without knowing x
+        (Token::Op(op), _) if op == "&&" => {
+            let unknown = || EvalError::UnknownOperator(op.clone());
+            eval_and(&args, &mut |arg| as_value(arg).ok_or_else(unknown)).ok()
+        }
+        (Token::Op(op), Some(values)) => apply_value(op, &values).ok(),
+        (Token::Symbol(name), Some(values)) => {
+            let numbers = values.iter().map(|value| match value {
+                Value::Number(n) => Some(*n),
+                Value::Bool(_) => None,
+            });
+            let numbers = numbers.collect::<Option<Vec<_>>>();
+            numbers.and_then(|numbers| call(name, &numbers).ok().map(Value::Number))
+        }
+        _ => None,
+    };
+    let folded = Expr::Cons(head.clone(), args);
+    let step = folded.display_as(DisplayStyle::Infix).to_string();
+    match value {
+        Some(value) => {
+            steps.push(format!("{} = {}", step, value));
+            match value {
+                Value::Number(n) => number(n),
+                Value::Bool(b) => Expr::Atom(Token::Symbol(b.to_string())),
+            }
+        }
+        None => {
+            steps.push(step);
+            folded
+        }
+    }
+}
+
+fn as_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) if as_bool(s).is_some() => as_bool(s).map(Value::Bool),
+        _ => as_number(expr).map(Value::Number),
+    }
+}
+
+// number build the Expr of a computed value, a negative value is a negation
+// just like a parsed one.
+pub(crate) fn number(value: f64) -> Expr {
+    let atom = Expr::Atom(Token::Symbol(value.abs().to_string()));
+    if value.is_sign_negative() && value != 0.0 {
+        Expr::Cons(Token::Op("-".to_string()), vec![atom])
+    } else {
+        atom
+    }
+}
+
+// as_number return the value of a number literal or a negated one
+pub(crate) fn as_number(expr: &Expr) -> Option<f64> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) if is_number(s) => parse_number(s),
+        Expr::Cons(Token::Op(op), args) if op == "-" && args.len() == 1 => {
+            as_number(&args[0]).map(|value| -value)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::{flatten, parse, parse_where, parse_with, LexOptions};
+
+    fn eval_str(input: &str) -> Result<f64, EvalError> {
+        eval(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_eval() {
+        assert_eq!(eval_str("1 + 2 * 3"), Ok(7.0));
+        assert_eq!(eval_str("-2 ^ 2"), Ok(-4.0));
+        assert_eq!(eval_str("(1 + 2) / 4"), Ok(0.75));
+        assert_eq!(eval_str("10 - 3 - 2"), Ok(5.0));
+        assert_eq!(eval_str("8 / 4 / 2"), Ok(1.0));
+        assert_eq!(eval_str("√9 * 2"), Ok(6.0));
+        assert_eq!(eval_str("1 / 0"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            eval_str("x + 1"),
+            Err(EvalError::UndefinedVariable("x".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_flattened() {
+        let s = flatten(parse("1 + 2 + 3 * 4 * 5").unwrap());
+        assert_eq!(eval(&s), Ok(63.0));
+    }
+
+    #[test]
+    fn test_call() {
+        assert_eq!(eval_str("max(1, 2) * sqrt(16)"), Ok(8.0));
+        assert_eq!(eval_str("2 * pi"), Ok(2.0 * PI));
+        assert_eq!(eval_str("ln(e)"), Ok(1.0));
+        assert_eq!(
+            eval_str("max(1)"),
+            Err(EvalError::WrongArgumentCount("max".to_string()))
+        );
+        assert_eq!(
+            eval_str("f(1)"),
+            Err(EvalError::UnknownFunction("f".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_is_constant() {
+        assert!(parse("2 * pi").unwrap().is_constant());
+        assert!(!parse("2 * x").unwrap().is_constant());
+        assert!(parse("sqrt(2) + max(e, |-1|)").unwrap().is_constant());
+        assert!(!parse("sqrt(x)").unwrap().is_constant());
+        assert!(!parse("f(1)").unwrap().is_constant());
+        assert!(parse("true").unwrap().is_constant());
+        assert!(parse("1 < 2 && false").unwrap().is_constant());
+        assert!(!parse("true && x").unwrap().is_constant());
+    }
+
+    #[test]
+    fn test_digit_grouping() {
+        let options = LexOptions {
+            digit_grouping: true,
+            ..Default::default()
+        };
+        let s = parse_with("1,000 + 500", &options).unwrap();
+        assert_eq!(eval(&s), Ok(1500.0));
+
+        let s = parse("max(1, 2)").unwrap();
+        assert_eq!(eval(&s), Ok(2.0));
+        let s = parse("max(1,200)").unwrap();
+        assert_eq!(eval(&s), Ok(200.0));
+        let s = parse_with("max(1,200, 3)", &options).unwrap();
+        assert_eq!(eval(&s), Ok(1200.0));
+    }
+
+    #[test]
+    fn test_partial_eval() {
+        let expr = parse("x * y + 1").unwrap();
+        let vars = HashMap::from([("x".to_string(), 2.0)]);
+        assert_eq!(partial_eval(&expr, &vars).to_string(), "(+ (* 2 y) 1)");
+
+        let vars = HashMap::from([("x".to_string(), 2.0), ("y".to_string(), -3.0)]);
+        let s = partial_eval(&expr, &vars);
+        assert_eq!(s.to_string(), "(- 5)");
+        assert_eq!(eval(&s), Ok(-5.0));
+
+        let expr = parse("(1 + 2) * x / (y - y)").unwrap();
+        let vars = HashMap::from([("y".to_string(), 1.0)]);
+        assert_eq!(partial_eval(&expr, &vars).to_string(), "(/ (* 3 x) 0)");
+    }
+
+    #[test]
+    fn test_evaluation_steps() {
+        let steps = |input| evaluation_steps(&parse(input).unwrap());
+        assert_eq!(steps("1 + 2 * 3"), ["2 * 3 = 6", "1 + 6 = 7"]);
+        assert_eq!(steps("true && false"), ["true && false = false"]);
+        assert_eq!(
+            steps("1 < 2 && 3 > 4"),
+            ["1 < 2 = true", "3 > 4 = false", "true && false = false"]
+        );
+        assert_eq!(
+            steps("0 < 1 < 2"),
+            ["0 < 1 = true", "1 < 2 = true", "true && true = true"]
+        );
+        assert_eq!(steps("false && x"), ["false && x = false"]);
+        assert_eq!(steps("x && false"), ["x && false"]);
+        assert_eq!(
+            steps("max(-1, 4 / 8) ^ 2 > 0"),
+            [
+                "4 / 8 = 0.5",
+                "max(-1, 0.5) = 0.5",
+                "0.5 ^ 2 = 0.25",
+                "0.25 > 0 = true"
+            ]
+        );
+        assert_eq!(
+            steps("(x + 2 * 3) * |1 - 5|"),
+            [
+                "2 * 3 = 6",
+                "x + 6",
+                "1 - 5 = -4",
+                "|-4| = 4",
+                "(x + 6) * 4"
+            ]
+        );
+        assert_eq!(steps("-1 / 0"), ["-1 / 0"]);
+        assert!(steps("-2").is_empty());
+    }
+
+    #[test]
+    fn test_eval_where() {
+        let eval_str = |input| eval_where(&parse_where(input).unwrap());
+        assert_eq!(eval_str("x * 2 where x = 3"), Ok(Value::Number(6.0)));
+        assert_eq!(eval_str("x + y where x = 1, y = 2"), Ok(Value::Number(3.0)));
+        assert_eq!(
+            eval_str("x * 2 where x = y + 1, y = max(3, 2)"),
+            Ok(Value::Number(8.0))
+        );
+        assert_eq!(eval_str("1 + 2"), Ok(Value::Number(3.0)));
+        // booleans
+        assert_eq!(eval_str("x < 2 where x = 1"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval_str("b && x > 1 where b = x < 3, x = 2"),
+            Ok(Value::Bool(true))
+        );
+        assert_eq!(
+            eval_str("x where x = y, y = x + 1"),
+            Err(EvalError::UndefinedVariable("y".to_string()))
+        );
+        assert_eq!(
+            eval_str("x + z where x = 1"),
+            Err(EvalError::UndefinedVariable("z".to_string()))
+        );
+        assert_eq!(
+            eval_str("x where x = 1 / 0"),
+            Err(EvalError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn test_eval_numeric() {
+        let expr = parse("sqrt(4) + x").unwrap();
+        assert_eq!(eval_numeric(&expr).to_string(), "(+ 2 x)");
+
+        // partial_eval leaves calls alone
+        let vars = HashMap::new();
+        assert_eq!(partial_eval(&expr, &vars).to_string(), "(+ (sqrt 4) x)");
+
+        let expr = parse("max(1, 2) * x + |3 - 5| * sin(x)").unwrap();
+        let s = eval_numeric(&expr);
+        assert_eq!(s.to_string(), "(+ (* 2 x) (* 2 (sin x)))");
+
+        let expr = parse("sqrt(16) + max(2, 3) ^ 2").unwrap();
+        assert_eq!(eval_numeric(&expr).to_string(), "13");
+
+        // errors and unknown functions are left to eval
+        let expr = parse("f(1) + sqrt(1, 2)").unwrap();
+        assert_eq!(eval_numeric(&expr).to_string(), "(+ (f 1) (sqrt 1 2))");
+    }
+
+    #[test]
+    fn test_ascii_digits() {
+        assert_eq!(eval_str("123"), Ok(123.0));
+        assert_eq!(eval_str(".5 + 1"), Ok(1.5));
+        assert_eq!(
+            eval_str("١٢٣ + 1"),
+            Err(EvalError::InvalidNumber("١٢٣".to_string()))
+        );
+        assert_eq!(
+            eval_str("1٢"),
+            Err(EvalError::InvalidNumber("1٢".to_string()))
+        );
+        assert_eq!(
+            eval_str("."),
+            Err(EvalError::UndefinedVariable(".".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_si_prefix() {
+        assert_eq!(eval_str("3k + 500"), Ok(3500.0));
+        assert_eq!(eval_str("2M"), Ok(2e6));
+        assert_eq!(eval_str("1.5G / 1k"), Ok(1.5e6));
+        // only a number can have a prefix, and a unit is not a prefix
+        assert_eq!(
+            eval_str("k"),
+            Err(EvalError::UndefinedVariable("k".to_string()))
+        );
+        assert_eq!(
+            eval_str("3km"),
+            Err(EvalError::InvalidNumber("3km".to_string()))
+        );
+        assert_eq!(
+            eval_str("3K"),
+            Err(EvalError::InvalidNumber("3K".to_string()))
+        );
+
+        let s = partial_eval(&parse("2k * 3 + x").unwrap(), &HashMap::new());
+        assert_eq!(s.to_string(), "(+ 6000 x)");
+    }
+
+    #[test]
+    fn test_coalesce() {
+        assert_eq!(eval_str("x ?? 0"), Ok(0.0));
+        assert_eq!(eval_str("x ?? y ?? 2 + 1"), Ok(3.0));
+        assert_eq!(eval_str("pi ?? 0"), Ok(std::f64::consts::PI));
+        let vars = HashMap::from([("x".to_string(), 5.0)]);
+        let expr = partial_eval(&parse("1 + (x ?? 0)").unwrap(), &vars);
+        assert_eq!(expr.to_string(), "6");
+        let expr = partial_eval(&parse("y ?? x").unwrap(), &vars);
+        assert_eq!(expr.to_string(), "(?? y 5)");
+        // only an undefined variable falls back
+        assert_eq!(eval_str("1 / 0 ?? 2"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            eval_str("f(1) ?? 2"),
+            Err(EvalError::UnknownFunction("f".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_bool() {
+        let eval_value_str = |input| eval_value(&parse(input).unwrap());
+        assert_eq!(eval_value_str("true"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value_str("false"), Ok(Value::Bool(false)));
+        assert_eq!(eval_value_str("true && false"), Ok(Value::Bool(false)));
+        assert_eq!(eval_value_str("true && (1 > 0)"), Ok(Value::Bool(true)));
+        assert_eq!(
+            eval_value_str("1 + 1 == 2 && 2 <= 1"),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(eval_value_str("2 * 3"), Ok(Value::Number(6.0)));
+        // the right operand isn't evaluated once the left one is false
+        assert_eq!(eval_value_str("false && x"), Ok(Value::Bool(false)));
+
+        assert_eq!(
+            eval_value_str("true + 1"),
+            Err(EvalError::InvalidOperand("+".to_string()))
+        );
+        assert_eq!(
+            eval_value_str("1 && true"),
+            Err(EvalError::InvalidOperand("&&".to_string()))
+        );
+        assert_eq!(
+            eval_str("1 < 2"),
+            Err(EvalError::NotANumber("(< 1 2)".to_string()))
+        );
+
+        // "true" is a keyword, not a variable
+        let vars = HashMap::from([("true".to_string(), 0.0)]);
+        let s = partial_eval(&parse("true && x").unwrap(), &vars);
+        assert_eq!(s.to_string(), "(&& true x)");
+    }
+
+    #[test]
+    fn test_comparison_chain() {
+        let eval_value_str = |input| eval_value(&parse(input).unwrap());
+        assert_eq!(eval_value_str("1 < 5 < 10"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value_str("1 < 15 < 10"), Ok(Value::Bool(false)));
+        assert_eq!(eval_value_str("1 < 2 <= 2 == 2"), Ok(Value::Bool(true)));
+        assert_eq!(eval_value_str("(1 < 2) == true"), Ok(Value::Bool(true)));
+
+        // the middle operand is evaluated once
+        let s = parse("1 < 2 * 3 < 10").unwrap();
+        let Expr::Cons(_, args) = &s else {
+            panic!("not a cons: {}", s)
+        };
+        let middle = parse("2 * 3").unwrap();
+        let mut count = 0;
+        let value = eval_and(args, &mut |expr| {
+            if *expr == middle {
+                count += 1;
+            }
+            eval_value(expr)
+        });
+        assert_eq!(value, Ok(Value::Bool(true)));
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_angle() {
+        assert!((eval_str("sin(90deg)").unwrap() - 1.0).abs() < 1e-12);
+        assert_eq!(eval_str("90deg"), Ok(PI / 2.0));
+        assert_eq!(eval_str("180 deg - pi rad"), Ok(0.0));
+    }
+
+    #[test]
+    fn test_division_mode() {
+        let eval_str = |input, division| eval_with_division(&parse(input).unwrap(), division);
+        use DivisionMode::*;
+        assert_eq!(eval_str("7 / 2", Float), Ok(3.5));
+        assert_eq!(eval_str("7 / 2", IntegerFloor), Ok(3.0));
+        assert_eq!(eval_str("7 / 2", IntegerTrunc), Ok(3.0));
+        assert_eq!(eval_str("-7 / 2", Float), Ok(-3.5));
+        assert_eq!(eval_str("-7 / 2", IntegerFloor), Ok(-4.0));
+        assert_eq!(eval_str("-7 / 2", IntegerTrunc), Ok(-3.0));
+        // operands are integers by value, "3 * 2.5" is 7.5 but "2 * 2.5" is 5
+        for mode in [Float, IntegerFloor, IntegerTrunc] {
+            assert_eq!(eval_str("7.5 / 2", mode), Ok(3.75));
+            assert_eq!(eval_str("1 / 0.4", mode), Ok(2.5));
+            assert_eq!(eval_str("3 * 2.5 / 3", mode), Ok(2.5));
+            assert_eq!(eval_str("1 / 0", mode), Err(EvalError::DivisionByZero));
+        }
+        assert_eq!(eval_str("2 * 2.5 / 2", IntegerFloor), Ok(2.0));
+        assert_eq!(eval_str("max(9 / 2, 1)", IntegerFloor), Ok(4.0));
+    }
+
+    #[test]
+    fn test_eval_memoized() {
+        let calls = Rc::new(Cell::new(0));
+        let mut functions = Functions::new();
+        let counted = calls.clone();
+        functions.register("slow", move |args| {
+            counted.set(counted.get() + 1);
+            Ok(args.iter().sum())
+        });
+        let expr = parse("slow(1, 2) * slow(1, 2) + (slow(1, 2) ?? 0) - slow(2, 1)").unwrap();
+        assert_eq!(eval_memoized(&expr, &functions), Ok(9.0));
+        assert_eq!(calls.get(), 2);
+        // the memo only lasts for one eval
+        assert_eq!(eval_memoized(&expr, &functions), Ok(9.0));
+        assert_eq!(calls.get(), 4);
+        assert_eq!(eval_with(&expr, &functions), Ok(9.0));
+        assert_eq!(calls.get(), 8);
+    }
+
+    #[test]
+    fn test_functions() {
+        let mut functions = Functions::new();
+        functions.register("hypot", |args| match args {
+            [a, b] => Ok(a.hypot(*b)),
+            _ => Err(EvalError::WrongArgumentCount("hypot".to_string())),
+        });
+        // hide the builtin one
+        functions.register("ln", |_| Ok(0.0));
+        let eval_str = |input| eval_with(&parse(input).unwrap(), &functions);
+        assert_eq!(eval_str("hypot(3, 4) + sqrt(hypot(6, 8) * 10)"), Ok(15.0));
+        assert_eq!(eval_str("ln(e)"), Ok(0.0));
+        assert_eq!(
+            eval_str("hypot(1)"),
+            Err(EvalError::WrongArgumentCount("hypot".to_string()))
+        );
+        assert_eq!(
+            eval_str("f(1)"),
+            Err(EvalError::UnknownFunction("f".to_string()))
+        );
+        // eval only knows the builtin ones
+        assert_eq!(
+            eval(&parse("hypot(3, 4)").unwrap()),
+            Err(EvalError::UnknownFunction("hypot".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_abs() {
+        assert_eq!(eval_str("|-3|"), Ok(3.0));
+        assert_eq!(eval_str("|1 - 4| * 2"), Ok(6.0));
+        assert_eq!(eval_str("||-2| - 5|"), Ok(3.0));
+    }
+}
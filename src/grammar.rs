@@ -0,0 +1,230 @@
+use std::{error, fmt};
+
+use crate::{
+    lexer::LexOptions,
+    parser::{
+        parse_spanned_with, Associativity, Context, Expr, Fixity, OperatorInfo, ParseError,
+        KEYWORDS, OPERATORS,
+    },
+};
+
+// symbols the parser or the lexer gives a meaning of their own, they can't be
+// operators. "=" binds a variable of a "where" clause and "\"" starts a string.
+const RESERVED: &[&str] = &["(", ")", "[", "]", "|", ",", "", "\"", "="];
+
+// comment and line continuation markers, the lexer blanks them before it sees
+// any operator
+const MARKERS: &[&str] = &["#", "/*", "*/", "\\"];
+
+// a symbol can't be lexed as one token if it has a space in it or if it starts
+// like a number
+fn is_reserved(symbol: &str) -> bool {
+    RESERVED.contains(&symbol)
+        || KEYWORDS.contains(&symbol)
+        || MARKERS.iter().any(|marker| symbol.contains(marker))
+        || symbol.chars().any(char::is_whitespace)
+        || symbol.starts_with(|c: char| c.is_ascii_digit())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GrammarError {
+    // an operator is registered twice in different ways, or it's a paren
+    ConflictingOperator(String),
+}
+
+impl fmt::Display for GrammarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GrammarError::ConflictingOperator(op) => write!(f, "conflicting operator: {}", op),
+        }
+    }
+}
+
+impl error::Error for GrammarError {}
+
+// GrammarBuilder register operators on top of the builtin ones. A symbol can
+// be both a prefix and an infix operator like "-", registering it again with
+// the same fixity must give the same precedence and associativity.
+#[derive(Debug, Clone)]
+pub struct GrammarBuilder {
+    operators: Vec<OperatorInfo>,
+}
+
+impl Default for GrammarBuilder {
+    fn default() -> Self {
+        GrammarBuilder::new()
+    }
+}
+
+impl GrammarBuilder {
+    pub fn new() -> GrammarBuilder {
+        GrammarBuilder {
+            operators: OPERATORS.to_vec(),
+        }
+    }
+
+    pub fn prefix(mut self, symbol: &'static str, precedence: u8) -> GrammarBuilder {
+        self.operators.push(OperatorInfo {
+            symbol,
+            fixity: Fixity::Prefix,
+            precedence,
+            associativity: Associativity::Right,
+        });
+        self
+    }
+
+    pub fn infix(
+        mut self,
+        symbol: &'static str,
+        precedence: u8,
+        associativity: Associativity,
+    ) -> GrammarBuilder {
+        self.operators.push(OperatorInfo {
+            symbol,
+            fixity: Fixity::Infix,
+            precedence,
+            associativity,
+        });
+        self
+    }
+
+    pub fn build(self) -> Result<Grammar, GrammarError> {
+        let mut operators: Vec<OperatorInfo> = vec![];
+        for op in self.operators {
+            if is_reserved(op.symbol) {
+                return Err(GrammarError::ConflictingOperator(op.symbol.to_string()));
+            }
+            // both follow an operand, "1 deg 2" can't tell a postfix from an
+            // infix
+            let follows = |fixity| matches!(fixity, Fixity::Infix | Fixity::Postfix);
+            if operators.iter().any(|known| {
+                known.symbol == op.symbol
+                    && known.fixity != op.fixity
+                    && follows(known.fixity)
+                    && follows(op.fixity)
+            }) {
+                return Err(GrammarError::ConflictingOperator(op.symbol.to_string()));
+            }
+            match operators
+                .iter()
+                .find(|known| known.symbol == op.symbol && known.fixity == op.fixity)
+            {
+                Some(known) if *known != op => {
+                    return Err(GrammarError::ConflictingOperator(op.symbol.to_string()))
+                }
+                Some(_) => {}
+                None => operators.push(op),
+            }
+        }
+        Ok(Grammar { operators })
+    }
+}
+
+// Grammar is a table of operators built by GrammarBuilder, parse with it to
+// recognize custom operators. Printing an expression uses the builtin table,
+// "(1 + 2) % 3" prints as "(% (+ 1 2) 3)" but to_tokens and the infix style
+// write the custom "%" as a call, "%(1 + 2)(3)".
+#[derive(Debug, Clone)]
+pub struct Grammar {
+    operators: Vec<OperatorInfo>,
+}
+
+impl Grammar {
+    pub fn parse(&self, input: &str) -> Result<Expr, ParseError> {
+        let mut ctx = Context::with_operators(&self.operators);
+        parse_spanned_with(input, &LexOptions::default(), &mut ctx).map(|(expr, _)| expr)
+    }
+
+    pub fn operators(&self) -> &[OperatorInfo] {
+        &self.operators
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::DisplayStyle;
+
+    #[test]
+    fn test_grammar() {
+        let grammar = GrammarBuilder::new()
+            .infix("%", 20, Associativity::Left)
+            .infix("mod", 20, Associativity::Left)
+            .infix("<<", 5, Associativity::Left)
+            .prefix("!", 30)
+            .build()
+            .unwrap();
+        let s = grammar.parse("7 % 3 + 1").unwrap();
+        assert_eq!(s.to_string(), "(+ (% 7 3) 1)");
+        let s = grammar.parse("7 mod 3 << !x*2").unwrap();
+        assert_eq!(s.to_string(), "(<< (mod 7 3) (* (! x) 2))");
+        // the longest operator wins, "!=" isn't a "!" followed by "="
+        let s = grammar.parse("!x != 1").unwrap();
+        assert_eq!(s.to_string(), "(!= (! x) 1)");
+        // printing doesn't know about custom operators
+        let s = grammar.parse("(1 + 2) % 3").unwrap();
+        assert_eq!(s.display_as(DisplayStyle::Infix).to_string(), "%(1 + 2)(3)");
+        // builtin operators are still there
+        assert_eq!(grammar.operators().len(), OPERATORS.len() + 4);
+    }
+
+    #[test]
+    fn test_conflicting_operator() {
+        let err = GrammarBuilder::new()
+            .infix("%", 20, Associativity::Left)
+            .infix("%", 30, Associativity::Left)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, GrammarError::ConflictingOperator("%".to_string()));
+
+        // "*" is already a left associative infix
+        let err = GrammarBuilder::new()
+            .infix("*", 20, Associativity::Right)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, GrammarError::ConflictingOperator("*".to_string()));
+
+        for symbol in ["(", "", "\"", "="] {
+            let err = GrammarBuilder::new()
+                .infix(symbol, 20, Associativity::Left)
+                .build()
+                .unwrap_err();
+            assert_eq!(err, GrammarError::ConflictingOperator(symbol.to_string()));
+        }
+
+        for symbol in [
+            "#", "/*", "*/", "\\", "a#b", " ", "a b", "1", "2x", "true", "where",
+        ] {
+            let err = GrammarBuilder::new()
+                .infix(symbol, 20, Associativity::Left)
+                .build()
+                .unwrap_err();
+            assert_eq!(err, GrammarError::ConflictingOperator(symbol.to_string()));
+        }
+        assert!(GrammarBuilder::new().prefix("false", 30).build().is_err());
+        assert!(GrammarBuilder::new().prefix("x1", 30).build().is_ok());
+
+        // "deg" is already a postfix
+        let err = GrammarBuilder::new()
+            .infix("deg", 20, Associativity::Left)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, GrammarError::ConflictingOperator("deg".to_string()));
+        assert!(GrammarBuilder::new().prefix("deg", 30).build().is_ok());
+
+        // a prefix and an infix can share a symbol, a registration can repeat
+        let grammar = GrammarBuilder::new()
+            .prefix("~", 30)
+            .infix("~", 10, Associativity::Left)
+            .infix("+", 10, Associativity::Left)
+            .build()
+            .unwrap();
+        let s = grammar.parse("~1 ~ 2 - -3").unwrap();
+        assert_eq!(s.to_string(), "(- (~ (~ 1) 2) (- 3))");
+        assert!(GrammarBuilder::new()
+            .prefix("-", 30)
+            .infix("-", 10, Associativity::Left)
+            .build()
+            .is_ok());
+    }
+}
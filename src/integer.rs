@@ -0,0 +1,126 @@
+use crate::{
+    eval::{as_bool, EvalError},
+    lexer::{is_number, Token},
+    parser::Expr,
+};
+
+// eval_i128 is eval but compute with i128 integers, "/" is an integer
+// division truncating toward zero. An operation overflowing i128 is an
+// EvalError::Overflow instead of wrapping around. There are no booleans,
+// "true" is EvalError::NotANumber.
+pub fn eval_i128(expr: &Expr) -> Result<i128, EvalError> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) if is_number(s) => s
+            .parse()
+            .map_err(|_| EvalError::InvalidNumber(s.to_string())),
+        Expr::Atom(Token::Symbol(s)) if as_bool(s).is_some() => {
+            Err(EvalError::NotANumber(s.clone()))
+        }
+        Expr::Atom(Token::Symbol(s)) => Err(EvalError::UndefinedVariable(s.clone())),
+        Expr::Cons(head, args) => {
+            let args = args.iter().map(eval_i128).collect::<Result<Vec<_>, _>>()?;
+            match head {
+                Token::Symbol(name) => call(name, &args),
+                _ => apply(&head.to_string(), &args),
+            }
+        }
+        Expr::Atom(token) => Err(EvalError::UnknownOperator(token.to_string())),
+    }
+}
+
+fn apply(op: &str, args: &[i128]) -> Result<i128, EvalError> {
+    let value = match (op, args) {
+        ("+", [a]) => Some(*a),
+        ("-", [a]) => a.checked_neg(),
+        ("+", [a, b]) => a.checked_add(*b),
+        ("-", [a, b]) => a.checked_sub(*b),
+        ("*", [a, b]) => a.checked_mul(*b),
+        ("/", [_, 0]) => return Err(EvalError::DivisionByZero),
+        ("/", [a, b]) => a.checked_div(*b),
+        // a negative exponent doesn't give an integer
+        ("^", [_, b]) if *b < 0 => return Err(EvalError::InvalidOperand(op.to_string())),
+        // powers of these never overflow, however big the exponent
+        ("^", [0, b]) => Some(if *b == 0 { 1 } else { 0 }),
+        ("^", [1, _]) => Some(1),
+        ("^", [-1, b]) => Some(if b % 2 == 0 { 1 } else { -1 }),
+        ("^", [a, b]) => u32::try_from(*b).ok().and_then(|b| a.checked_pow(b)),
+        ("+", [_, _, ..]) => args.iter().try_fold(0i128, |sum, x| sum.checked_add(*x)),
+        ("*", [_, _, ..]) => args
+            .iter()
+            .try_fold(1i128, |product, x| product.checked_mul(*x)),
+        _ => return Err(EvalError::UnknownOperator(op.to_string())),
+    };
+    value.ok_or(EvalError::Overflow)
+}
+
+fn call(name: &str, args: &[i128]) -> Result<i128, EvalError> {
+    match (name, args) {
+        ("abs", [x]) => x.checked_abs().ok_or(EvalError::Overflow),
+        ("min", [a, b]) => Ok(*a.min(b)),
+        ("max", [a, b]) => Ok(*a.max(b)),
+        ("abs" | "min" | "max", _) => Err(EvalError::WrongArgumentCount(name.to_string())),
+        _ => Err(EvalError::UnknownFunction(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn eval_str(input: &str) -> Result<i128, EvalError> {
+        eval_i128(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_eval_i128() {
+        assert_eq!(eval_str("1000000 * 1000000"), Ok(1_000_000_000_000));
+        assert_eq!(eval_str("2 ^ 70"), Ok(1 << 70));
+        assert_eq!(eval_str("max(|-3|, 2) - 10"), Ok(-7));
+        assert_eq!(
+            eval_str("1.5"),
+            Err(EvalError::InvalidNumber("1.5".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(eval_str("2 ^ 127"), Err(EvalError::Overflow));
+        assert_eq!(eval_str("-(2 ^ 126) * 2"), Ok(i128::MIN));
+        assert_eq!(eval_str("-(2 ^ 126) * 2 - 1"), Err(EvalError::Overflow));
+        assert_eq!(eval_str("|-(2 ^ 126) * 2|"), Err(EvalError::Overflow));
+        assert_eq!(eval_str("2 ^ 5000000000"), Err(EvalError::Overflow));
+        assert_eq!(eval_str("1 ^ 5000000000"), Ok(1));
+        assert_eq!(eval_str("0 ^ 5000000000"), Ok(0));
+        assert_eq!(eval_str("0 ^ 0"), Ok(1));
+        assert_eq!(eval_str("(-1) ^ 5000000000"), Ok(1));
+        assert_eq!(eval_str("(-1) ^ 5000000001"), Ok(-1));
+        assert_eq!(
+            eval_str("2 ^ -1"),
+            Err(EvalError::InvalidOperand("^".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_division() {
+        assert_eq!(eval_str("7 / 2"), Ok(3));
+        assert_eq!(eval_str("-7 / 2"), Ok(-3));
+        assert_eq!(eval_str("7 / (2 - 2)"), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(
+            eval_str("true"),
+            Err(EvalError::NotANumber("true".to_string()))
+        );
+        assert_eq!(
+            eval_str("1 + false"),
+            Err(EvalError::NotANumber("false".to_string()))
+        );
+        assert_eq!(
+            eval_str("x"),
+            Err(EvalError::UndefinedVariable("x".to_string()))
+        );
+    }
+}
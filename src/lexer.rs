@@ -0,0 +1,679 @@
+use std::{error, fmt, ops::Range};
+
+use regex::Regex;
+
+// Token is either a Symbol like "1", "2", or an Op like "+", "*". A quoted
+// string literal is a Str holding its unescaped contents. Whitespace and
+// comments are Trivia, they are only kept with LexOptions::preserve_trivia
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Token {
+    Symbol(String),
+    Op(String),
+    Str(String),
+    Trivia(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Symbol(s) => write!(f, "{}", s),
+            Token::Op(op) => write!(f, "{}", op),
+            Token::Str(s) => write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"")),
+            Token::Trivia(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Token {
+    // return the right paren a left one must be closed by, "[" is grouping
+    // just like "(", and "|" is closed by another "|"
+    pub(crate) fn closing_paren(&self) -> Option<&'static str> {
+        match self {
+            Self::Op(op) => match op.as_str() {
+                "(" => Some(")"),
+                "[" => Some("]"),
+                "|" => Some("|"),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+    pub(crate) fn is_right_paren(&self) -> bool {
+        match self {
+            Self::Op(op) => matches!(op.as_str(), ")" | "]" | "|"),
+            _ => false,
+        }
+    }
+    pub(crate) fn is_op(&self, s: &str) -> bool {
+        matches!(self, Self::Op(op) if op == s)
+    }
+    // a name is a symbol that isn't a number, like a variable or function
+    pub(crate) fn is_name(&self) -> bool {
+        match self {
+            Self::Symbol(s) => !is_number(s),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexError {
+    UnterminatedComment,
+    UnterminatedString,
+    // input has more tokens than LexOptions::max_tokens
+    TooManyTokens,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedComment => write!(f, "unterminated block comment"),
+            LexError::UnterminatedString => write!(f, "unterminated string literal"),
+            LexError::TooManyTokens => write!(f, "too many tokens"),
+        }
+    }
+}
+
+impl error::Error for LexError {}
+
+// Span is the range of bytes a token spans in the input
+pub type Span = Range<usize>;
+
+// is_number check if a symbol is meant to be a number, it starts with an
+// ASCII digit or a "." followed by one. It may still be an invalid one like
+// "1.2.3", other digits like "١٢٣" make a name.
+pub(crate) fn is_number(s: &str) -> bool {
+    s.strip_prefix('.')
+        .unwrap_or(s)
+        .starts_with(|c: char| c.is_ascii_digit())
+}
+
+// split_number split a symbol into the number it starts with and the rest,
+// "1.5e3m" is "1.5e3" and "m". An "e" not followed by an exponent like the
+// one of "2em" is a part of the rest. Like the lexer, which splits "1e-3"
+// at "-", an exponent has no sign.
+pub(crate) fn split_number(s: &str) -> (&str, &str) {
+    let mut end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    if let Some(exponent) = s[end..].strip_prefix(['e', 'E']) {
+        let len = exponent
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(exponent.len());
+        if len > 0 {
+            end += 1 + len;
+        }
+    }
+    s.split_at(end)
+}
+
+// LexOptions tweak how an input is split into tokens
+#[derive(Debug, Clone, Default)]
+pub struct LexOptions {
+    // stop with LexError::TooManyTokens once the input has more tokens than
+    // this, so memory used by tokens is bounded for a huge input. Trivia
+    // tokens don't count.
+    pub max_tokens: Option<usize>,
+    // keep whitespace and comments between tokens as Trivia, so the input can
+    // be reconstructed from its tokens
+    pub preserve_trivia: bool,
+    // read numbers like "1,000,000" with commas between groups of three
+    // digits as one number. A comma is then only an argument separator when
+    // it isn't followed by exactly three digits, "max(1,000)" has one argument.
+    pub digit_grouping: bool,
+    // operator chars a name can have, an operator made of them like the "-"
+    // of "--my-var" is a part of the name when it's right next to a name or
+    // another one. It doesn't classify other chars, one like "$" that isn't
+    // an operator is already lexed as a part of a name.
+    pub identifier_char: Option<fn(char) -> bool>,
+}
+
+pub(crate) struct Lexer {
+    tokens: Vec<(Token, Span)>,
+    // span of the last popped token
+    span: Span,
+    // whether the last popped token closed a "()" or "[]" group
+    after_group: bool,
+}
+impl Lexer {
+    // with_operators split tokens at the builtin operators and the given ones
+    pub(crate) fn with_operators(
+        input: &str,
+        options: &LexOptions,
+        operators: &[&str],
+    ) -> Result<Lexer, LexError> {
+        let mut tokens = tokenize_spanned(input, options, operators)?;
+        // parse tokens from left to right, reverse tokens here so that we can
+        // pop out first token without shifting all elements.
+        tokens.reverse();
+        Ok(Lexer {
+            tokens,
+            span: 0..0,
+            after_group: false,
+        })
+    }
+
+    // pop the first token of origin input, from left to right, trivia is
+    // skipped
+    pub(crate) fn pop(&mut self) -> Option<Token> {
+        let (token, span) = loop {
+            match self.tokens.pop()? {
+                (Token::Trivia(_), _) => continue,
+                popped => break popped,
+            }
+        };
+        self.span = span;
+        self.after_group = matches!(&token, Token::Op(op) if op == ")" || op == "]");
+        Some(token)
+    }
+    // return span of the last popped token
+    pub(crate) fn span(&self) -> Span {
+        self.span.clone()
+    }
+    // check first token without pop out it
+    pub(crate) fn peek(&self) -> Option<&Token> {
+        self.tokens
+            .iter()
+            .rev()
+            .map(|(token, _)| token)
+            .find(|token| !matches!(token, Token::Trivia(_)))
+    }
+    // return span of the first token without pop out it
+    pub(crate) fn peek_span(&self) -> Option<Span> {
+        self.tokens
+            .iter()
+            .rev()
+            .find(|(token, _)| !matches!(token, Token::Trivia(_)))
+            .map(|(_, span)| span.clone())
+    }
+    // return the number of tokens not popped out yet, trivia excluded
+    pub(crate) fn len(&self) -> usize {
+        self.tokens
+            .iter()
+            .filter(|(token, _)| !matches!(token, Token::Trivia(_)))
+            .count()
+    }
+    // take the tokens not popped out yet, from left to right
+    pub(crate) fn into_rest(self) -> Vec<Token> {
+        self.tokens
+            .into_iter()
+            .rev()
+            .map(|(token, _)| token)
+            .filter(|token| !matches!(token, Token::Trivia(_)))
+            .collect()
+    }
+    // check if the next token opens a group right after another group closed,
+    // like the ")(" in "(1+2)(3+4)"
+    pub(crate) fn is_adjacent_group(&self) -> bool {
+        self.after_group && matches!(self.peek(), Some(Token::Op(op)) if op == "(" || op == "[")
+    }
+}
+
+// tokenize split an input into tokens
+pub fn tokenize(input: &str, options: &LexOptions) -> Result<Vec<Token>, LexError> {
+    let tokens = tokenize_spanned(input, options, &[])?;
+    Ok(tokens.into_iter().map(|(token, _)| token).collect())
+}
+
+// BUILTIN_OPS are the operators always split into their own token
+const BUILTIN_OPS: &[&str] = &[
+    "+", "-", "*", "/", "^", "(", ")", "[", "]", "|", ",", "√", "**", "×", "÷", "−", "&&", "<",
+    ">", "<=", ">=", "==", "!=", "??",
+];
+
+pub(crate) fn tokenize_spanned(
+    input: &str,
+    options: &LexOptions,
+    operators: &[&str],
+) -> Result<Vec<(Token, Span)>, LexError> {
+    let stripped = strip_comments(input)?;
+    // other operators made of punctuation split tokens just like builtin
+    // ones, a word like "mod" is lexed as a symbol.
+    let extra = operators
+        .iter()
+        .copied()
+        .filter(|op| !BUILTIN_OPS.contains(op))
+        .filter(|op| op.starts_with(|c: char| !(c.is_alphanumeric() || c == '_')))
+        .collect::<Vec<_>>();
+    let words = operators
+        .iter()
+        .copied()
+        .filter(|op| op.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+        .collect::<Vec<_>>();
+    // operators of more than one char are matched first, the longest one wins
+    // like "**" over "*"
+    let mut long_ops = BUILTIN_OPS
+        .iter()
+        .filter(|op| op.chars().count() > 1)
+        .chain(&extra)
+        .collect::<Vec<_>>();
+    long_ops.sort_by_key(|op| std::cmp::Reverse(op.len()));
+    let long_ops = long_ops
+        .iter()
+        .map(|op| regex::escape(op) + "|")
+        .collect::<String>();
+    let extra_chars = extra
+        .iter()
+        .filter_map(|op| op.chars().next())
+        .map(|c| regex::escape(&c.to_string()))
+        .collect::<String>();
+    // a token is either a string literal, an operator or a run of chars until
+    // the next operator, quote or whitespace. A char starting an operator but
+    // not making one, like a single "&", is a symbol of its own.
+    let mut pattern = format!(
+        r#"{}"|{}[\-+*/^()\[\]|,×÷−√<>]|[^\s\-+*/^()\[\]|,×÷−√"<>&=!?{}]+|\S"#,
+        STRING, long_ops, extra_chars
+    );
+    if options.digit_grouping {
+        pattern.insert_str(0, r"\b\d{1,3}(?:,\d{3})+(?:\.\d+)?\b|");
+    }
+    let re = Regex::new(&pattern).unwrap();
+    let mut tokens = vec![];
+    // trivia isn't counted, there is at most one between two tokens
+    let mut significant = 0;
+    let mut push = |token: Token, span| {
+        if !matches!(token, Token::Trivia(_)) {
+            if options.max_tokens.is_some_and(|max| significant >= max) {
+                return Err(LexError::TooManyTokens);
+            }
+            significant += 1;
+        }
+        tokens.push((token, span));
+        Ok(())
+    };
+    let mut end = 0;
+    for m in re.find_iter(&stripped) {
+        // comments are blanked out at the same position, so the original
+        // trivia is still at where the whitespace is
+        if options.preserve_trivia && m.start() > end {
+            push(
+                Token::Trivia(input[end..m.start()].to_string()),
+                end..m.start(),
+            )?;
+        }
+        end = m.end();
+        let c = m.as_str();
+        let token = match c {
+            "+" | "-" | "*" | "/" | "^" | "(" | ")" | "[" | "]" | "|" | "," | "√" | "&&" | "<"
+            | ">" | "<=" | ">=" | "==" | "!=" | "??" => Token::Op(c.to_string()),
+            "**" | "×" | "÷" | "−" => Token::Op(canonical(c).to_string()),
+            _ if extra.contains(&c) => Token::Op(c.to_string()),
+            _ if c.starts_with('"') => Token::Str(unescape(&c[1..c.len() - 1])),
+            _ if options.digit_grouping && c.contains(',') => Token::Symbol(c.replace(',', "")),
+            _ => Token::Symbol(c.to_string()),
+        };
+        // a word operator right after the digits of a number is a token of
+        // its own, "90deg" is "90" then "deg"
+        let split = words.iter().find_map(|word| {
+            let digits = c.strip_suffix(word)?;
+            let is_digits =
+                !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit() || c == '.');
+            (is_digits && is_number(digits)).then_some(digits.len())
+        });
+        if let Some(at) = split {
+            let at = m.start() + at;
+            push(
+                Token::Symbol(input[m.start()..at].to_string()),
+                m.start()..at,
+            )?;
+            push(Token::Symbol(input[at..m.end()].to_string()), at..m.end())?;
+            continue;
+        }
+        push(token, m.range())?;
+    }
+    if options.preserve_trivia && input.len() > end {
+        push(Token::Trivia(input[end..].to_string()), end..input.len())?;
+    }
+    match options.identifier_char {
+        Some(is_identifier) => Ok(merge_identifiers(input, tokens, is_identifier)),
+        None => Ok(tokens),
+    }
+}
+
+// merge_identifiers join adjacent tokens into a name when operators made of
+// identifier chars are among them. Two names alone like the "90" and "deg"
+// of "90deg" are kept apart.
+fn merge_identifiers(
+    input: &str,
+    tokens: Vec<(Token, Span)>,
+    is_identifier: fn(char) -> bool,
+) -> Vec<(Token, Span)> {
+    let mut merged: Vec<(Token, Span)> = vec![];
+    // whether the last token can be joined, and whether it has an identifier
+    // operator in it
+    let mut last_joinable = false;
+    let mut joined = false;
+    for (token, span) in tokens {
+        let is_op = matches!(token, Token::Op(_)) && input[span.clone()].chars().all(is_identifier);
+        let joinable = is_op || matches!(token, Token::Symbol(_));
+        match merged.last_mut() {
+            Some((last, last_span))
+                if last_joinable
+                    && joinable
+                    && last_span.end == span.start
+                    && (joined || is_op) =>
+            {
+                last_span.end = span.end;
+                *last = Token::Symbol(input[last_span.clone()].to_string());
+                joined = true;
+            }
+            _ => {
+                merged.push((token, span));
+                last_joinable = joinable;
+                joined = is_op;
+            }
+        }
+    }
+    merged
+}
+
+// canonical return the operator an alias stands for, or op itself
+pub(crate) fn canonical(op: &str) -> &str {
+    match op {
+        "**" => "^",
+        "×" => "*",
+        "÷" => "/",
+        "−" => "-",
+        _ => op,
+    }
+}
+
+// STRING matches the opening quote and contents of a string literal, a quote
+// or a backslash is escaped by a backslash
+const STRING: &str = r#""(?:[^"\\]|\\(?s:.))*"#;
+
+// unescape return the contents of a string literal, only "\"" and "\\" are
+// escapes, any other backslash is kept
+fn unescape(s: &str) -> String {
+    let mut output = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some(next @ ('"' | '\\'))) => {
+                output.push(next);
+                chars.next();
+            }
+            _ => output.push(c),
+        }
+    }
+    output
+}
+
+// strip_comments blank out "#" line comments and "/* */" block comments.
+// Block comments don't nest, a "/*" inside a block comment is ignored and the
+// first "*/" closes it. Comments are replaced by spaces of the same length so
+// the remaining text stays at its original position. String literals are
+// kept, a "#" inside them isn't a comment. A "\" ending a line is blanked
+// too, so the expression continues on the next line, one ending the input
+// continues on nothing.
+fn strip_comments(input: &str) -> Result<String, LexError> {
+    let pattern = format!(
+        r#"(?s){}("?)|#[^\n]*|/\*.*?\*/|/\*|\\[ \t]*(?:\r?\n|$)"#,
+        STRING
+    );
+    let re = Regex::new(&pattern).unwrap();
+    let mut output = String::with_capacity(input.len());
+    let mut end = 0;
+    for caps in re.captures_iter(input) {
+        let m = caps.get(0).unwrap();
+        output.push_str(&input[end..m.start()]);
+        end = m.end();
+        match m.as_str() {
+            s if s.starts_with('"') && caps[1].is_empty() => {
+                return Err(LexError::UnterminatedString)
+            }
+            s if s.starts_with('"') => output.push_str(s),
+            // a terminated block comment is matched first, so this "/*"
+            // doesn't have its "*/"
+            "/*" => return Err(LexError::UnterminatedComment),
+            s => output.push_str(&" ".repeat(s.len())),
+        }
+    }
+    output.push_str(&input[end..]);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Result<Vec<String>, LexError> {
+        let mut lexer = Lexer::with_operators(input, &LexOptions::default(), &[])?;
+        let mut tokens = vec![];
+        while let Some(token) = lexer.pop() {
+            tokens.push(token.to_string());
+        }
+        Ok(tokens)
+    }
+
+    #[test]
+    fn test_span() {
+        let mut lexer =
+            Lexer::with_operators("12 +(345) /* */ x", &LexOptions::default(), &[]).unwrap();
+        let mut spans = vec![];
+        while lexer.pop().is_some() {
+            spans.push(lexer.span());
+        }
+        assert_eq!(spans, [0..2, 3..4, 4..5, 5..8, 8..9, 16..17]);
+    }
+
+    #[test]
+    fn test_unicode_operator() {
+        let tokens = tokenize("2×3÷−4", &LexOptions::default()).unwrap();
+        let tokens = tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(tokens, ["2", "*", "3", "/", "-", "4"]);
+
+        let mut lexer = Lexer::with_operators("2 × 3", &LexOptions::default(), &[]).unwrap();
+        lexer.pop();
+        lexer.pop();
+        assert_eq!(lexer.span(), 2..4);
+    }
+
+    #[test]
+    fn test_digit_grouping() {
+        let options = LexOptions {
+            digit_grouping: true,
+            ..Default::default()
+        };
+        let tokens = |input| {
+            let mut lexer = Lexer::with_operators(input, &options, &[]).unwrap();
+            let mut tokens = vec![];
+            while let Some(token) = lexer.pop() {
+                tokens.push((token.to_string(), lexer.span()));
+            }
+            tokens
+        };
+        assert_eq!(tokens("1,000,000.5"), [("1000000.5".to_string(), 0..11)]);
+        assert_eq!(
+            tokens("f(1, 2,345)")
+                .into_iter()
+                .map(|(t, _)| t)
+                .collect::<Vec<_>>(),
+            ["f", "(", "1", ",", "2345", ")"]
+        );
+        // not groups of three digits
+        for input in ["1,0000", "1234,567", "x1,000", "1,00"] {
+            assert!(tokens(input).iter().any(|(t, _)| t == ","), "{}", input);
+        }
+
+        let tokens = tokenize("1,000", &LexOptions::default()).unwrap();
+        assert_eq!(tokens.len(), 3);
+    }
+
+    #[test]
+    fn test_double_star() {
+        let tokens = tokenize("2***3**-4", &LexOptions::default()).unwrap();
+        let tokens = tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        assert_eq!(tokens, ["2", "^", "*", "3", "^", "-", "4"]);
+    }
+
+    #[test]
+    fn test_identifier_char() {
+        let tokens = |input, is_identifier| {
+            let options = LexOptions {
+                identifier_char: Some(is_identifier),
+                ..Default::default()
+            };
+            let tokens = tokenize(input, &options).unwrap();
+            tokens.iter().map(Token::to_string).collect::<Vec<_>>()
+        };
+        let default = |input| {
+            let tokens = tokenize(input, &LexOptions::default()).unwrap();
+            tokens.iter().map(Token::to_string).collect::<Vec<_>>()
+        };
+        let root = |c| c == '√';
+        assert_eq!(
+            default("a√b + √(2)"),
+            ["a", "√", "b", "+", "√", "(", "2", ")"]
+        );
+        assert_eq!(tokens("a√b + √(2)", root), ["a√b", "+", "√", "(", "2", ")"]);
+        // a char that isn't an operator changes nothing
+        let dollar = |c| c == '$';
+        assert_eq!(default("a$b+$"), ["a$b", "+", "$"]);
+        assert_eq!(tokens("a$b+$", dollar), ["a$b", "+", "$"]);
+
+        let css = |c| c == '-' || c == '$';
+        assert_eq!(
+            tokens("--my-var - 1*-x", css),
+            ["--my-var", "-", "1", "*", "-x"]
+        );
+        assert_eq!(tokens("x-1 -(2)", css), ["x-1", "-", "(", "2", ")"]);
+        // by default "-" is an operator
+        assert_eq!(default("--my-var"), ["-", "-", "my", "-", "var"]);
+    }
+
+    #[test]
+    fn test_trivia() {
+        let options = LexOptions {
+            preserve_trivia: true,
+            ..Default::default()
+        };
+        let input = "1 +  2 /* two */";
+        let tokens = tokenize(input, &options).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::Symbol("1".to_string()),
+                Token::Trivia(" ".to_string()),
+                Token::Op("+".to_string()),
+                Token::Trivia("  ".to_string()),
+                Token::Symbol("2".to_string()),
+                Token::Trivia(" /* two */".to_string()),
+            ]
+        );
+        let joined = tokens.iter().map(|t| t.to_string()).collect::<String>();
+        assert_eq!(joined, input);
+
+        let mut lexer = Lexer::with_operators(input, &options, &[]).unwrap();
+        assert_eq!(lexer.peek(), Some(&Token::Symbol("1".to_string())));
+        lexer.pop();
+        assert_eq!(lexer.pop(), Some(Token::Op("+".to_string())));
+        assert_eq!(lexer.span(), 2..3);
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let options = LexOptions {
+            max_tokens: Some(5),
+            ..Default::default()
+        };
+        let lexer = Lexer::with_operators("1 + 2 * 3", &options, &[]).unwrap();
+        assert_eq!(lexer.tokens.len(), 5);
+
+        // stop at the 6th token instead of collecting all of them
+        let input = "1 + ".repeat(100_000);
+        assert_eq!(
+            Lexer::with_operators(&input, &options, &[]).err(),
+            Some(LexError::TooManyTokens)
+        );
+
+        // trivia doesn't count
+        let options = LexOptions {
+            max_tokens: Some(5),
+            preserve_trivia: true,
+            ..Default::default()
+        };
+        let tokens = tokenize(" 1 + 2 * 3 # end", &options).unwrap();
+        assert_eq!(tokens.len(), 11);
+        assert_eq!(
+            tokenize("1 + 2 * 3 + 4", &options),
+            Err(LexError::TooManyTokens)
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(tokens("1 + /* two */ 2").unwrap(), ["1", "+", "2"]);
+        assert_eq!(tokens("1 /* a # b */ * 2 # 3").unwrap(), ["1", "*", "2"]);
+        assert_eq!(tokens("1 # /* \n+ 2").unwrap(), ["1", "+", "2"]);
+        assert_eq!(tokens("1 + /* 2"), Err(LexError::UnterminatedComment));
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        assert_eq!(
+            tokens("1 + \\\n2 + \\ \r\n3").unwrap(),
+            ["1", "+", "2", "+", "3"]
+        );
+        assert_eq!(tokens("1 # \\\n+ 2").unwrap(), ["1", "+", "2"]);
+        // kept in a string, the escaped backslash is displayed
+        assert_eq!(
+            tokens("\"a\\\nb\" \\ 2").unwrap(),
+            ["\"a\\\\\nb\"", "\\", "2"]
+        );
+    }
+
+    #[test]
+    fn test_is_number() {
+        for s in ["123", "0.5", ".5", "1e3", "1.2.3", "3km"] {
+            assert!(is_number(s), "{}", s);
+        }
+        for s in ["١٢٣", "٣", "x1", ".", ".x", "-1"] {
+            assert!(!is_number(s), "{}", s);
+        }
+        assert!(Token::Symbol("١٢٣".to_string()).is_name());
+    }
+
+    #[test]
+    fn test_comparison() {
+        assert_eq!(
+            tokens("1<=x&&x!=2").unwrap(),
+            ["1", "<=", "x", "&&", "x", "!=", "2"]
+        );
+        assert_eq!(
+            tokens("a<b>c==d").unwrap(),
+            ["a", "<", "b", ">", "c", "==", "d"]
+        );
+        // not an operator, but still a token of its own
+        assert_eq!(tokens("a & b=c").unwrap(), ["a", "&", "b", "=", "c"]);
+        assert_eq!(tokens("a??b?c").unwrap(), ["a", "??", "b", "?", "c"]);
+    }
+
+    #[test]
+    fn test_string() {
+        let tokens = tokenize(r#""hello world" + "x # /* y""#, &LexOptions::default()).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Token::Str("hello world".to_string()),
+                Token::Op("+".to_string()),
+                Token::Str("x # /* y".to_string()),
+            ]
+        );
+
+        let tokens = tokenize(r#"f("say \"hi\"", "a\\b\n")"#, &LexOptions::default()).unwrap();
+        assert_eq!(tokens[2], Token::Str(r#"say "hi""#.to_string()));
+        assert_eq!(tokens[4], Token::Str(r"a\b\n".to_string()));
+        // displayed escaped, so it's lexed back to the same string
+        assert_eq!(tokens[4].to_string(), r#""a\\b\\n""#);
+
+        assert_eq!(
+            tokenize(r#"1 + "hello"#, &LexOptions::default()),
+            Err(LexError::UnterminatedString)
+        );
+        assert_eq!(
+            tokenize(r#""a\""#, &LexOptions::default()),
+            Err(LexError::UnterminatedString)
+        );
+    }
+}
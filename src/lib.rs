@@ -1,2 +1,39 @@
+mod bytes;
+#[cfg(feature = "complex")]
+mod complex;
+mod diagnostic;
+mod eval;
+mod grammar;
+mod integer;
+mod lexer;
+mod macros;
 mod parser;
-pub use parser::{parse, Expr, Token};
+#[cfg(feature = "rational")]
+mod rational;
+mod transform;
+mod units;
+mod yaml;
+pub use bytes::DecodeError;
+#[cfg(feature = "complex")]
+pub use complex::{eval_complex, Complex};
+pub use diagnostic::{redundant_parens, render_error, Diagnostic};
+pub use eval::{
+    eval, eval_memoized, eval_numeric, eval_value, eval_where, eval_with, eval_with_division,
+    evaluation_steps, partial_eval, DivisionMode, EvalError, Functions, Value,
+};
+pub use grammar::{Grammar, GrammarBuilder, GrammarError};
+pub use integer::eval_i128;
+pub use lexer::{tokenize, LexError, LexOptions, Span, Token};
+pub use parser::{
+    longest_valid_prefix, node_at, parse, parse_lenient, parse_lines, parse_restricted,
+    parse_spanned, parse_until, parse_where, parse_with, parse_with_stats, supported_operators,
+    AllowList, Associativity, DisplayStyle, Expr, Fixity, OperatorInfo, ParseError, ParseStats,
+    Parser, SpanTree, Styled, Where,
+};
+#[cfg(feature = "rational")]
+pub use rational::{eval_rational, Rational};
+pub use transform::{
+    canonical_hash, contains, cse, diff, flatten, is_commutative, max_arity, over_arity,
+    parenthesizations, rename_var, Change, Cse, MAX_PARENTHESIZED,
+};
+pub use units::{eval_units, Dimension, Quantity};
@@ -0,0 +1,24 @@
+// expr build an Expr from tokens written in Rust, expr!(1 + 2 * 3) is the
+// same as parse("1 + 2 * 3"). It panics if the tokens aren't a valid
+// expression.
+#[macro_export]
+macro_rules! expr {
+    ($($t:tt)+) => {
+        $crate::parse(stringify!($($t)+)).expect("invalid expression")
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    #[test]
+    fn test_expr() {
+        assert_eq!(expr!(1 + 2), parse("1 + 2").unwrap());
+        assert_eq!(expr!(1 + 2 * 3), parse("1 + 2 * 3").unwrap());
+        assert_eq!(expr!(-(a + b) * c), parse("-(a + b) * c").unwrap());
+        assert_eq!(expr!(2 ^ -3 ^ 2), parse("2 ^ -3 ^ 2").unwrap());
+        assert_eq!(expr!(max(1, |x - 1|)), parse("max(1, |x - 1|)").unwrap());
+        assert_eq!(expr!([1 + 2](3)).to_string(), "(* (+ 1 2) 3)");
+    }
+}
@@ -1,5 +1,8 @@
 use simple_tdop::parse;
 
 fn main() {
-    println!("{}", parse("1+2*3"));
+    match parse("1+2*3") {
+        Ok(expr) => println!("{}", expr),
+        Err(err) => eprintln!("{}", err),
+    }
 }
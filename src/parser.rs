@@ -1,180 +1,2027 @@
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error, fmt,
+    io::{self, BufRead},
+};
 
-use regex::Regex;
+use crate::{
+    diagnostic::Diagnostic,
+    lexer::{canonical, tokenize, tokenize_spanned, LexError, LexOptions, Lexer, Span, Token},
+    transform::ASSOCIATIVE,
+};
 
-// Token is either a Symbol like "1", "2", or an Op like "+", "*"
-#[derive(Debug, Clone)]
-pub enum Token {
-    Symbol(String),
-    Op(String),
+impl Token {
+    // nud return right binding power
+    pub(crate) fn nud(&self) -> Option<u8> {
+        nud(OPERATORS, self)
+    }
+
+    // led return left and right binding power
+    pub(crate) fn led(&self) -> Option<(u8, u8)> {
+        led(OPERATORS, self)
+    }
+
+    // postfix return left binding power of a postfix operator
+    pub(crate) fn postfix(&self) -> Option<u8> {
+        postfix(OPERATORS, self)
+    }
+}
+
+// nud and led look up binding powers of token from a table of operators, a
+// symbol can be an operator word like "mod"
+fn nud(operators: &[OperatorInfo], token: &Token) -> Option<u8> {
+    find_operator(operators, token, Fixity::Prefix).map(|op| op.binding_power().1)
+}
+
+fn led(operators: &[OperatorInfo], token: &Token) -> Option<(u8, u8)> {
+    find_operator(operators, token, Fixity::Infix).map(OperatorInfo::binding_power)
+}
+
+fn postfix(operators: &[OperatorInfo], token: &Token) -> Option<u8> {
+    find_operator(operators, token, Fixity::Postfix).map(|op| op.binding_power().0)
+}
+
+// Fixity tells where an operator is placed relative to its operands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fixity {
+    Prefix,
+    Infix,
+    Postfix,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+// OperatorInfo describes an operator recognized by the parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub symbol: &'static str,
+    pub fixity: Fixity,
+    pub precedence: u8,
+    pub associativity: Associativity,
+}
+
+const fn prefix(symbol: &'static str, precedence: u8) -> OperatorInfo {
+    OperatorInfo {
+        symbol,
+        fixity: Fixity::Prefix,
+        precedence,
+        associativity: Associativity::Right,
+    }
+}
+
+const fn postfix_op(symbol: &'static str, precedence: u8) -> OperatorInfo {
+    OperatorInfo {
+        symbol,
+        fixity: Fixity::Postfix,
+        precedence,
+        associativity: Associativity::Left,
+    }
+}
+
+const fn infix(symbol: &'static str, precedence: u8, associativity: Associativity) -> OperatorInfo {
+    OperatorInfo {
+        symbol,
+        fixity: Fixity::Infix,
+        precedence,
+        associativity,
+    }
 }
 
-impl fmt::Display for Token {
+// OPERATORS is the table nud and led look up binding powers from, unless a
+// Grammar has its own
+pub(crate) const OPERATORS: &[OperatorInfo] = &[
+    prefix("+", 30),
+    prefix("-", 30),
+    prefix("√", 30),
+    // "a ?? b" is b if a has an undefined variable
+    infix("??", 1, Associativity::Right),
+    infix("&&", 3, Associativity::Left),
+    infix("<", 5, Associativity::Left),
+    infix(">", 5, Associativity::Left),
+    infix("<=", 5, Associativity::Left),
+    infix(">=", 5, Associativity::Left),
+    infix("==", 5, Associativity::Left),
+    infix("!=", 5, Associativity::Left),
+    infix("+", 10, Associativity::Left),
+    infix("-", 10, Associativity::Left),
+    infix("*", 20, Associativity::Left),
+    infix("/", 20, Associativity::Left),
+    // "^" binds tighter than a prefix "-" so that "-2 ^ 2" is "(- (^ 2 2))"
+    infix("^", 40, Associativity::Right),
+    // angle units, "90deg" is converted to radians
+    postfix_op("deg", 50),
+    postfix_op("rad", 50),
+];
+
+// KEYWORDS are words the parser reads as values or clauses, they can't be
+// operators or bound by "where"
+pub(crate) const KEYWORDS: &[&str] = &["true", "false", "where"];
+
+// COMPARISONS are the operators chaining like "1 < x <= 10"
+pub(crate) const COMPARISONS: &[&str] = &["<", ">", "<=", ">=", "==", "!="];
+
+fn find_operator<'a>(
+    operators: &'a [OperatorInfo],
+    token: &Token,
+    fixity: Fixity,
+) -> Option<&'a OperatorInfo> {
+    let symbol = match token {
+        Token::Op(s) | Token::Symbol(s) => s,
+        _ => return None,
+    };
+    operators
+        .iter()
+        .find(|op| op.symbol == symbol && op.fixity == fixity)
+}
+
+impl OperatorInfo {
+    // binding_power return left and right binding power of the operator.
+    // parse_bp stops taking operators once their left binding power is lower
+    // than the right one it's called with, so a left associative operator
+    // needs a higher right binding power to stop at the same operator on its
+    // right, "1 - 2 - 3" is then "(- (- 1 2) 3)".
+    pub(crate) fn binding_power(&self) -> (u8, u8) {
+        match (self.fixity, self.associativity) {
+            (Fixity::Prefix, _) => (0, self.precedence),
+            (Fixity::Postfix, _) => (self.precedence, 0),
+            (Fixity::Infix, Associativity::Left) => (self.precedence, self.precedence + 1),
+            (Fixity::Infix, Associativity::Right) => (self.precedence, self.precedence),
+        }
+    }
+}
+
+// supported_operators list all operators the parser recognizes
+pub fn supported_operators() -> Vec<OperatorInfo> {
+    OPERATORS.to_vec()
+}
+
+// Expr is a lisp S-expression, it's either an atom or a list of atom.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expr {
+    Atom(Token),
+    Cons(Token, Vec<Expr>),
+}
+
+impl fmt::Display for Expr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Token::Symbol(s) => write!(f, "{}", s),
-            Token::Op(op) => write!(f, "{}", op),
+            Expr::Atom(t) => write!(f, "{}", t),
+            Expr::Cons(head, rest) => {
+                write!(f, "({}", head)?;
+                for s in rest {
+                    write!(f, " {}", s)?
+                }
+                write!(f, ")")
+            }
         }
     }
 }
 
-impl Token {
-    fn is_left_paren(&self) -> bool {
+impl Expr {
+    // debug_truncated print the S-expression down to max_depth levels, the
+    // children of a node at the last level are replaced by "…". At depth 2
+    // "1 + 2 * (3 - 4)" is "(+ 1 (* …))".
+    pub fn debug_truncated(&self, max_depth: usize) -> String {
+        let mut s = String::new();
+        self.write_truncated(max_depth, &mut s);
+        s
+    }
+
+    fn write_truncated(&self, max_depth: usize, s: &mut String) {
         match self {
-            Self::Op(op) => op == "(",
-            _ => false,
+            _ if max_depth == 0 => s.push('…'),
+            Expr::Atom(t) => s.push_str(&t.to_string()),
+            Expr::Cons(head, rest) => {
+                s.push_str(&format!("({}", head));
+                if max_depth == 1 && !rest.is_empty() {
+                    s.push_str(" …");
+                } else {
+                    for child in rest {
+                        s.push(' ');
+                        child.write_truncated(max_depth - 1, s);
+                    }
+                }
+                s.push(')');
+            }
         }
     }
-    fn is_right_paren(&self) -> bool {
+
+    // to_tokens flatten the tree back into infix tokens, parentheses are
+    // inserted where binding powers require them so that parsing the tokens
+    // again gives the same tree.
+    pub fn to_tokens(&self) -> Vec<Token> {
+        let mut tokens = vec![];
+        self.write_tokens(&mut tokens);
+        tokens
+    }
+
+    // write_tokens return the lowest left binding power on the left edge of
+    // the written tokens and the lowest right binding power on the right edge,
+    // an operator next to them binding tighter would take a part of self away.
+    fn write_tokens(&self, tokens: &mut Vec<Token>) -> (u8, u8) {
         match self {
-            Self::Op(op) => op == ")",
-            _ => false,
+            Expr::Atom(token) => {
+                tokens.push(token.clone());
+                (u8::MAX, u8::MAX)
+            }
+            Expr::Cons(Token::Symbol(name), rest) if name == "abs" && rest.len() == 1 => {
+                tokens.push(Token::Op("|".to_string()));
+                rest[0].write_tokens(tokens);
+                tokens.push(Token::Op("|".to_string()));
+                (u8::MAX, u8::MAX)
+            }
+            Expr::Cons(name @ Token::Symbol(_), rest) => {
+                tokens.push(name.clone());
+                tokens.push(Token::Op("(".to_string()));
+                for (i, arg) in rest.iter().enumerate() {
+                    if i > 0 {
+                        tokens.push(Token::Op(",".to_string()));
+                    }
+                    arg.write_tokens(tokens);
+                }
+                tokens.push(Token::Op(")".to_string()));
+                (u8::MAX, u8::MAX)
+            }
+            Expr::Cons(op, rest) => match (rest.as_slice(), op.nud(), op.led()) {
+                ([operand], _, _) if op.postfix().is_some() => {
+                    let lbp = op.postfix().unwrap_or_default();
+                    let (left, _) = write_operand(operand, tokens, |_, rbp| rbp < lbp);
+                    tokens.push(op.clone());
+                    (left.min(lbp), u8::MAX)
+                }
+                ([operand], Some(rbp), _) => {
+                    tokens.push(op.clone());
+                    let (_, right) = write_operand(operand, tokens, |lbp, _| lbp < rbp);
+                    (u8::MAX, right.min(rbp))
+                }
+                ([left, right], _, Some(_)) => write_chain(&[left, right], &[op], tokens),
+                // a node of flatten or a comparison chain, "(+ 1 2 3)" is
+                // "1 + 2 + 3" and "(&& (< 0 x) (< x 1))" is "0 < x < 1"
+                ([_, _, _, ..], _, Some(_)) if matches!(op, Token::Op(s) if ASSOCIATIVE.contains(&s.as_str())) => {
+                    match comparison_chain(op, rest) {
+                        Some((operands, ops)) => write_chain(&operands, &ops, tokens),
+                        None => {
+                            let operands = rest.iter().collect::<Vec<_>>();
+                            write_chain(&operands, &vec![op; rest.len() - 1], tokens)
+                        }
+                    }
+                }
+                // not an operator call the parser would produce, group every
+                // operand after the operator
+                _ => {
+                    tokens.push(op.clone());
+                    for operand in rest {
+                        write_operand(operand, tokens, |_, _| true);
+                    }
+                    (u8::MAX, u8::MAX)
+                }
+            },
         }
     }
-    // nud return right binding power
-    fn nud(&self) -> Option<u8> {
+}
+
+// write_chain write operands separated by infix operators like "a + b - c",
+// an operand is parenthesized if an operator next to it binds tighter than
+// its edge. A comparison next to a comparison is always grouped, else they
+// would be parsed as a chain.
+fn write_chain(operands: &[&Expr], ops: &[&Token], tokens: &mut Vec<Token>) -> (u8, u8) {
+    let bps = ops
+        .iter()
+        .map(|op| op.led().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let (mut left, mut right) = (u8::MAX, u8::MAX);
+    for (i, operand) in operands.iter().enumerate() {
+        let before = i.checked_sub(1).map(|i| bps[i]);
+        let after = bps.get(i).copied();
+        let next_to_comparison = (i > 0 && is_comparison_op(ops[i - 1]))
+            || ops.get(i).is_some_and(|op| is_comparison_op(op));
+        let chained = next_to_comparison && is_comparison(operand);
+        let edges = write_operand(operand, tokens, |lbp, rbp| {
+            chained
+                || before.is_some_and(|(_, before_rbp)| lbp < before_rbp)
+                || after.is_some_and(|(after_lbp, _)| after_lbp >= rbp)
+        });
+        if i == 0 {
+            left = edges.0;
+        }
+        right = edges.1;
+        if let Some(op) = ops.get(i) {
+            tokens.push((*op).clone());
+        }
+    }
+    let lbp = bps.iter().map(|(lbp, _)| *lbp).min().unwrap_or(u8::MAX);
+    let rbp = bps.iter().map(|(_, rbp)| *rbp).min().unwrap_or(u8::MAX);
+    (left.min(lbp), right.min(rbp))
+}
+
+fn node_count(expr: &Expr) -> usize {
+    match expr {
+        Expr::Atom(_) => 1,
+        Expr::Cons(_, rest) => 1 + rest.iter().map(node_count).sum::<usize>(),
+    }
+}
+
+// comparison_chain return the operands and operators of a "&&" of
+// comparisons each sharing its left operand with the right one of the
+// previous, which the parser builds from "0 < x < y".
+fn comparison_chain<'a>(
+    op: &'a Token,
+    rest: &'a [Expr],
+) -> Option<(Vec<&'a Expr>, Vec<&'a Token>)> {
+    if !op.is_op("&&") {
+        return None;
+    }
+    let mut operands: Vec<&Expr> = vec![];
+    let mut ops = vec![];
+    for child in rest {
+        let Expr::Cons(cmp, pair) = child else {
+            return None;
+        };
+        let ([left, right], true) = (pair.as_slice(), is_comparison_op(cmp)) else {
+            return None;
+        };
+        match operands.last() {
+            None => operands.push(left),
+            Some(last) if *last == left => {}
+            Some(_) => return None,
+        }
+        ops.push(cmp);
+        operands.push(right);
+    }
+    Some((operands, ops))
+}
+
+fn is_comparison_op(token: &Token) -> bool {
+    matches!(token, Token::Op(op) if COMPARISONS.contains(&op.as_str()))
+}
+
+fn is_comparison(expr: &Expr) -> bool {
+    matches!(expr, Expr::Cons(op, rest) if is_comparison_op(op) && rest.len() == 2)
+}
+
+// write_operand write expr and surround it with parentheses when needs_paren
+// tells so given its edge binding powers
+fn write_operand(
+    expr: &Expr,
+    tokens: &mut Vec<Token>,
+    needs_paren: impl Fn(u8, u8) -> bool,
+) -> (u8, u8) {
+    let start = tokens.len();
+    let (lbp, rbp) = expr.write_tokens(tokens);
+    if needs_paren(lbp, rbp) {
+        tokens.insert(start, Token::Op("(".to_string()));
+        tokens.push(Token::Op(")".to_string()));
+        (u8::MAX, u8::MAX)
+    } else {
+        (lbp, rbp)
+    }
+}
+
+// DisplayStyle is how Expr::display_as writes an expression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayStyle {
+    // "(+ 1 (* 2 3))", the same as Display of Expr
+    Sexpr,
+    // "1 + 2 * 3", parenthesized like to_tokens
+    Infix,
+}
+
+// Styled is an Expr displayed in a given style
+pub struct Styled<'a> {
+    expr: &'a Expr,
+    style: DisplayStyle,
+}
+
+impl Expr {
+    pub fn display_as(&self, style: DisplayStyle) -> Styled<'_> {
+        Styled { expr: self, style }
+    }
+}
+
+impl fmt::Display for Styled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.style == DisplayStyle::Sexpr {
+            return write!(f, "{}", self.expr);
+        }
+        // operators are spaced out except prefix ones, a token after an
+        // opening paren or before a closing one or a comma sticks to it
+        let mut expect_operand = true;
+        let mut sticky = true;
+        for token in self.expr.to_tokens() {
+            let closing = token.is_op(",")
+                || token.is_op(")")
+                || token.is_op("]")
+                || (token.is_op("|") && !expect_operand);
+            let call = token.is_op("(") && !expect_operand;
+            if !(sticky || closing || call) {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", token)?;
+            (expect_operand, sticky) = match token {
+                Token::Op(op) if op == "," => (true, false),
+                Token::Op(_) if closing => (false, false),
+                Token::Op(_) if !expect_operand && token.postfix().is_some() => (false, false),
+                // opening parens and prefix operators
+                Token::Op(_) if expect_operand || call => (true, true),
+                Token::Op(_) => (true, false),
+                _ => (false, false),
+            };
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    Lex(LexError),
+    // a line of parse_lines can't be read, like one that isn't utf-8
+    Io(io::ErrorKind),
+    UnexpectedEof,
+    UnmatchedParen,
+    // a group is closed by a different kind of paren, like "(1]"
+    MismatchedParen {
+        expected: String,
+        found: Token,
+        span: Span,
+    },
+    UnexpectedToken {
+        expected: Expected,
+        found: Token,
+        span: Span,
+    },
+    // an operator or a function call denied by an AllowList
+    DisallowedOperator(String),
+    // a group with nothing inside like "()", a call like "f()" is fine
+    EmptyGroup {
+        span: Span,
+    },
+    // a variable bound twice by a "where" clause
+    DuplicateBinding {
+        name: String,
+        span: Span,
+    },
+}
+
+// Expected is what the parser was looking for when it found something else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    // a number, a name, a prefix operator or a group
+    Operand,
+    // an operator following an operand
+    Operator,
+    // a variable like the one bound by "where"
+    Name,
+    Token(String),
+    End,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Token::Op(s) => match s.as_str() {
-                "+" | "-" => Some(30),
-                _ => None,
-            },
-            _ => None,
+            Expected::Operand => write!(f, "an operand"),
+            Expected::Operator => write!(f, "an operator"),
+            Expected::Name => write!(f, "a name"),
+            Expected::Token(token) => write!(f, "'{}'", token),
+            Expected::End => write!(f, "end of input"),
         }
     }
+}
 
-    // led return left and right binding power
-    fn led(&self) -> Option<(u8, u8)> {
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Token::Op(s) => match s.as_str() {
-                "+" | "-" => Some((10, 10)),
-                "*" | "/" => Some((20, 20)),
-                _ => None,
-            },
+            ParseError::Lex(err) => write!(f, "{}", err),
+            ParseError::Io(kind) => write!(f, "cannot read line: {}", kind),
+            ParseError::UnexpectedEof => write!(f, "expected an operand but found end of input"),
+            ParseError::UnmatchedParen => write!(f, "unmatched parentheses"),
+            ParseError::MismatchedParen {
+                expected, found, ..
+            } => write!(
+                f,
+                "mismatched parentheses: expected '{}' but found '{}'",
+                expected, found
+            ),
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => {
+                write!(f, "expected {} but found '{}'", expected, found)
+            }
+            ParseError::DisallowedOperator(op) => write!(f, "operator '{}' is not allowed", op),
+            ParseError::EmptyGroup { .. } => write!(f, "empty group"),
+            ParseError::DuplicateBinding { name, .. } => {
+                write!(f, "variable '{}' is bound more than once", name)
+            }
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+impl ParseError {
+    // span of the offending token, errors found at the end of input or by the
+    // lexer have none
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::MismatchedParen { span, .. }
+            | ParseError::UnexpectedToken { span, .. }
+            | ParseError::EmptyGroup { span }
+            | ParseError::DuplicateBinding { span, .. } => Some(span.clone()),
             _ => None,
         }
     }
 }
 
-struct Lexer {
-    tokens: Vec<Token>,
+impl From<LexError> for ParseError {
+    fn from(err: LexError) -> Self {
+        ParseError::Lex(err)
+    }
 }
-impl Lexer {
-    fn new(input: &str) -> Lexer {
-        let re = Regex::new(r"([-+*/()])").unwrap();
-        let mut tokens = re
-            .replace_all(input, r" ${1} ")
-            .split_whitespace()
-            .map(|c| match c {
-                "+" | "-" | "*" | "/" | "(" | ")" => Token::Op(c.to_string()),
-                _ => Token::Symbol(c.to_string()),
-            })
-            .collect::<Vec<_>>();
-        // parse tokens from left to right, reverse tokens here so that we can
-        // pop out first token without shifting all elements.
-        tokens.reverse();
-        Lexer { tokens }
+
+// parse receive an input text and transform it to Expr
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    parse_with(input, &LexOptions::default())
+}
+
+// parse_with is parse but lex the input with the given options
+pub fn parse_with(input: &str, options: &LexOptions) -> Result<Expr, ParseError> {
+    parse_spanned_with(input, options, &mut Context::default()).map(|(expr, _)| expr)
+}
+
+// AllowList tells which operators parse_restricted accepts, everything is
+// allowed by default.
+#[derive(Debug, Clone, Default)]
+pub struct AllowList {
+    denied: HashSet<String>,
+    deny_calls: bool,
+}
+
+impl AllowList {
+    // deny an operator like "/", both its prefix and infix use, and its
+    // aliases like "÷". "|" denies absolute value bars, a function name
+    // denies calls to the function, "abs" denies bars too and "&&" denies
+    // comparison chains.
+    pub fn deny(mut self, op: &str) -> AllowList {
+        self.denied.insert(canonical(op).to_string());
+        self
     }
 
-    // pop the first token of origin input, from left to right
-    fn pop(&mut self) -> Option<Token> {
-        self.tokens.pop()
+    // deny every function call
+    pub fn deny_calls(mut self) -> AllowList {
+        self.deny_calls = true;
+        self
     }
-    // check first token without pop out it
-    fn peek(&mut self) -> Option<&Token> {
-        self.tokens.last()
+
+    // a word operator like "deg" is lexed as a symbol
+    fn check_op(&self, token: &Token) -> Result<(), ParseError> {
+        match token {
+            Token::Op(op) | Token::Symbol(op) if self.denied.contains(op) => {
+                Err(ParseError::DisallowedOperator(op.clone()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn check_call(&self, name: &Token) -> Result<(), ParseError> {
+        let name = name.to_string();
+        if self.deny_calls || self.denied.contains(&name) {
+            return Err(ParseError::DisallowedOperator(name));
+        }
+        Ok(())
     }
 }
 
-// Expr is a lisp S-expression, it's either an atom or a list of atom.
-pub enum Expr {
-    Atom(Token),
-    Cons(Token, Vec<Expr>),
+// parse_restricted is parse but fails with DisallowedOperator once an
+// operator denied by allow is found
+pub fn parse_restricted(input: &str, allow: &AllowList) -> Result<Expr, ParseError> {
+    let mut ctx = Context {
+        allow: allow.clone(),
+        ..Default::default()
+    };
+    parse_spanned_with(input, &LexOptions::default(), &mut ctx).map(|(expr, _)| expr)
 }
 
-impl fmt::Display for Expr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Expr::Atom(t) => write!(f, "{}", t),
-            Expr::Cons(head, rest) => {
-                write!(f, "({}", head)?;
-                for s in rest {
-                    write!(f, " {}", s)?
+// parse_lenient is parse but a group or call still open at the end of input
+// is closed, "(1 + 2" is "(+ 1 2)". Every auto-closed paren is reported as a
+// diagnostic.
+pub fn parse_lenient(input: &str) -> Result<(Expr, Vec<Diagnostic>), ParseError> {
+    let mut ctx = Context {
+        lenient: true,
+        ..Default::default()
+    };
+    let (expr, _) = parse_spanned_with(input, &LexOptions::default(), &mut ctx)?;
+    Ok((expr, ctx.diagnostics))
+}
+
+// parse_with_stats is parse but also measure the parse, stats of a failed
+// parse are what was measured until the error
+pub fn parse_with_stats(input: &str) -> (Result<Expr, ParseError>, ParseStats) {
+    let mut ctx = Context::default();
+    let parsed = parse_spanned_with(input, &LexOptions::default(), &mut ctx);
+    (parsed.map(|(expr, _)| expr), ctx.stats)
+}
+
+// parse_until parse only operators whose left binding power is at least
+// min_bp, and return the tokens left from the first one binding looser.
+// parse_until("1 * 2 + 3", 15) is "(* 1 2)" with "+ 3" left.
+pub fn parse_until(input: &str, min_bp: u8) -> Result<(Expr, Vec<Token>), ParseError> {
+    let symbols = OPERATORS.iter().map(|op| op.symbol).collect::<Vec<_>>();
+    let mut lexer = Lexer::with_operators(input, &LexOptions::default(), &symbols)?;
+    let (expr, _) = parse_bp(&mut lexer, &mut Context::default(), min_bp)?;
+    Ok((expr, lexer.into_rest()))
+}
+
+// Where is an expression with the variables bound by its "where" clause,
+// "x + y where x = 1, y = 2" binds x and y
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Where {
+    pub expr: Expr,
+    pub bindings: Vec<(String, Expr)>,
+}
+
+// parse_where parse an expression followed by an optional "where" and
+// bindings separated by commas, a binding is a name, a "=" and an expression.
+pub fn parse_where(input: &str) -> Result<Where, ParseError> {
+    let symbols = OPERATORS.iter().map(|op| op.symbol).collect::<Vec<_>>();
+    let mut lexer = Lexer::with_operators(input, &LexOptions::default(), &symbols)?;
+    let mut ctx = Context {
+        where_clause: true,
+        ..Default::default()
+    };
+    let (expr, _) = parse_bp(&mut lexer, &mut ctx, 0)?;
+    let mut bindings = vec![];
+    let mut next = lexer.pop();
+    if next == Some(Token::Symbol("where".to_string())) {
+        loop {
+            let found = lexer.pop().ok_or(ParseError::UnexpectedEof)?;
+            let keyword = matches!(&found, Token::Symbol(s) if KEYWORDS.contains(&s.as_str()));
+            if !found.is_name() || keyword || ctx.nud(&found).is_some() {
+                let span = lexer.span();
+                let expected = Expected::Name;
+                return Err(ParseError::UnexpectedToken {
+                    expected,
+                    found,
+                    span,
+                });
+            }
+            let name = found.to_string();
+            if bindings.iter().any(|(bound, _)| *bound == name) {
+                let span = lexer.span();
+                return Err(ParseError::DuplicateBinding { name, span });
+            }
+            match lexer.pop().ok_or(ParseError::UnexpectedEof)? {
+                Token::Symbol(s) if s == "=" => {}
+                found => {
+                    let span = lexer.span();
+                    let expected = Expected::Token("=".to_string());
+                    return Err(ParseError::UnexpectedToken {
+                        expected,
+                        found,
+                        span,
+                    });
                 }
-                write!(f, ")")
+            }
+            let (value, _) = parse_bp(&mut lexer, &mut ctx, 0)?;
+            bindings.push((name, value));
+            next = lexer.pop();
+            if !next.as_ref().is_some_and(|t| t.is_op(",")) {
+                break;
             }
         }
     }
+    match next {
+        Some(found) => Err(ParseError::UnexpectedToken {
+            expected: Expected::End,
+            found,
+            span: lexer.span(),
+        }),
+        None => Ok(Where { expr, bindings }),
+    }
 }
 
-// parse receive an input text and transform it to Expr
-pub fn parse(input: &str) -> Expr {
-    let mut lexer = Lexer::new(input);
-    parse_bp(&mut lexer, 0)
-}
-
-fn parse_bp(lexer: &mut Lexer, rbp: u8) -> Expr {
-    let token = lexer.pop().expect("unexpected eof");
-    let mut left = match token {
-        Token::Symbol(_) => Expr::Atom(token),
-        Token::Op(_) => {
-            if token.is_left_paren() {
-                let left = parse_bp(lexer, 0);
-                lexer.pop().expect("unmatch parentheses");
-                left
+// longest_valid_prefix return the expression of the longest prefix of input
+// that parses and its length in bytes, a prefix always ends with a token.
+// "1 + 2 +" gives "(+ 1 2)" and 5. It's None if no prefix parses. A string
+// or a comment left open is cut off, "1 + 2 /* x" gives "(+ 1 2)" too.
+pub fn longest_valid_prefix(input: &str) -> Option<(Expr, usize)> {
+    let symbols = OPERATORS.iter().map(|op| op.symbol).collect::<Vec<_>>();
+    let mut end = input.len();
+    let tokens = loop {
+        // the last quote or "/*" may be escaped or inside another string,
+        // then cut again at the one before it
+        match tokenize_spanned(&input[..end], &LexOptions::default(), &symbols) {
+            Ok(tokens) => break tokens,
+            Err(LexError::UnterminatedString) => end = input[..end].rfind('"')?,
+            Err(LexError::UnterminatedComment) => end = input[..end].rfind("/*")?,
+            Err(LexError::TooManyTokens) => return None,
+        }
+    };
+    tokens.iter().rev().find_map(|(_, span)| {
+        let expr = parse(&input[..span.end]).ok()?;
+        Some((expr, span.end))
+    })
+}
+
+// parse_lines parse every non-blank line of reader as an expression, a line
+// failing to parse doesn't stop the following ones. A line ending with "\"
+// or with a "(" or "[" left open continues on the next one. A line that isn't
+// utf-8 is an io error and is skipped, reading stops after any other io error.
+pub fn parse_lines<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Expr, ParseError>> {
+    let mut stopped = false;
+    let mut lines = reader.lines().map_while(move |line| {
+        if stopped {
+            return None;
+        }
+        Some(line.map_err(|err| {
+            stopped = err.kind() != io::ErrorKind::InvalidData;
+            ParseError::Io(err.kind())
+        }))
+    });
+    std::iter::from_fn(move || {
+        let mut expr = match lines.next()? {
+            Ok(line) => line,
+            Err(err) => return Some(Err(err)),
+        };
+        while expr.trim_end().ends_with('\\') || is_open(&expr) {
+            match lines.next() {
+                Some(Ok(line)) => {
+                    expr.push('\n');
+                    expr.push_str(&line);
+                }
+                Some(Err(err)) => return Some(Err(err)),
+                None => break,
+            }
+        }
+        Some(Ok(expr))
+    })
+    .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+    .map(|line| line.and_then(|line| parse(&line)))
+}
+
+// is_open tell whether input has more "(" and "[" than closing ones, parens
+// in strings and comments don't count
+fn is_open(input: &str) -> bool {
+    let Ok(tokens) = tokenize(input, &LexOptions::default()) else {
+        return false;
+    };
+    let depth = tokens.iter().fold(0, |depth, token| match token {
+        Token::Op(op) if op == "(" || op == "[" => depth + 1,
+        Token::Op(op) if op == ")" || op == "]" => depth - 1,
+        _ => depth,
+    });
+    depth > 0
+}
+
+// SpanTree has the same shape as the Expr parsed along with it, every node
+// keeps the range of source bytes covering all of its tokens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanTree {
+    pub span: Span,
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    fn leaf(span: Span) -> SpanTree {
+        SpanTree {
+            span,
+            children: vec![],
+        }
+    }
+}
+
+// parse_spanned is parse but also return where every node comes from
+pub fn parse_spanned(input: &str) -> Result<(Expr, SpanTree), ParseError> {
+    parse_spanned_with(input, &LexOptions::default(), &mut Context::default())
+}
+
+// Context is what a parse keeps besides its tokens
+pub(crate) struct Context<'a> {
+    // where binding powers are looked up
+    operators: &'a [OperatorInfo],
+    allow: AllowList,
+    // close groups left open at the end of input
+    lenient: bool,
+    diagnostics: Vec<Diagnostic>,
+    // how many parse_bp calls are running
+    depth: usize,
+    stats: ParseStats,
+    // a "where" ends the expression like a right paren
+    where_clause: bool,
+}
+
+// ParseStats is what parse_with_stats measures of a parse
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    // tokens of the input, trivia excluded
+    pub tokens: usize,
+    // deepest recursion reached
+    pub max_depth: usize,
+    // Expr nodes built, including those of a failed parse
+    pub nodes: usize,
+}
+
+impl Default for Context<'static> {
+    fn default() -> Self {
+        Context::with_operators(OPERATORS)
+    }
+}
+
+impl Context<'_> {
+    pub(crate) fn with_operators(operators: &[OperatorInfo]) -> Context<'_> {
+        Context {
+            operators,
+            allow: AllowList::default(),
+            lenient: false,
+            diagnostics: vec![],
+            depth: 0,
+            stats: ParseStats::default(),
+            where_clause: false,
+        }
+    }
+
+    fn nud(&self, token: &Token) -> Option<u8> {
+        nud(self.operators, token)
+    }
+
+    fn led(&self, token: &Token) -> Option<(u8, u8)> {
+        led(self.operators, token)
+    }
+
+    fn postfix(&self, token: &Token) -> Option<u8> {
+        postfix(self.operators, token)
+    }
+
+    // unclosed handle a group opened at start and not closed at the end of
+    // input, it's closed there if lenient
+    fn unclosed(&mut self, paren: &str, start: usize, end: usize) -> Result<(), ParseError> {
+        if !self.lenient {
+            return Err(ParseError::UnmatchedParen);
+        }
+        self.diagnostics.push(Diagnostic {
+            message: format!("unclosed '{}' closed at end of input", paren),
+            span: start..end,
+        });
+        Ok(())
+    }
+}
+
+pub(crate) fn parse_spanned_with(
+    input: &str,
+    options: &LexOptions,
+    ctx: &mut Context,
+) -> Result<(Expr, SpanTree), ParseError> {
+    let symbols = ctx.operators.iter().map(|op| op.symbol).collect::<Vec<_>>();
+    let mut lexer = Lexer::with_operators(input, options, &symbols)?;
+    ctx.stats.tokens = lexer.len();
+    let parsed = parse_bp(&mut lexer, ctx, 0)?;
+    // parse_bp stops at a right paren which has no left one
+    match lexer.pop() {
+        Some(found) => Err(ParseError::UnexpectedToken {
+            expected: Expected::End,
+            found,
+            span: lexer.span(),
+        }),
+        None => Ok(parsed),
+    }
+}
+
+// node_at return the innermost node of expr covering the byte offset
+pub fn node_at<'a>(expr: &'a Expr, spans: &SpanTree, offset: usize) -> Option<&'a Expr> {
+    if !spans.span.contains(&offset) {
+        return None;
+    }
+    if let Expr::Cons(_, rest) = expr {
+        for (child, child_spans) in rest.iter().zip(&spans.children) {
+            if let Some(node) = node_at(child, child_spans, offset) {
+                return Some(node);
+            }
+        }
+    }
+    Some(expr)
+}
+
+fn parse_bp(lexer: &mut Lexer, ctx: &mut Context, rbp: u8) -> Result<(Expr, SpanTree), ParseError> {
+    // an error ends the whole parse, so depth needn't be restored on it
+    ctx.depth += 1;
+    ctx.stats.max_depth = ctx.stats.max_depth.max(ctx.depth);
+    let token = lexer.pop().ok_or(ParseError::UnexpectedEof)?;
+    let start = lexer.span().start;
+    let (mut left, mut spans) = match token {
+        Token::Symbol(_)
+            if ctx.nud(&token).is_none()
+                && token.is_name()
+                && lexer.peek().is_some_and(|t| t.is_op("(")) =>
+        {
+            ctx.allow.check_call(&token)?;
+            parse_call(lexer, ctx, token, start)?
+        }
+        Token::Symbol(_) | Token::Str(_) if ctx.nud(&token).is_none() => {
+            ctx.stats.nodes += 1;
+            (Expr::Atom(token), SpanTree::leaf(lexer.span()))
+        }
+        // any token with a nud is a prefix operator, even if it has no led or
+        // it's a symbol. trivia is never popped out by the lexer.
+        _ => {
+            ctx.allow.check_op(&token)?;
+            if let Some(right_paren) = token.closing_paren() {
+                // parens of a call are parsed by parse_call, these ones group
+                // nothing. "||" is the opening of two bars, not an empty one.
+                if right_paren != "|" && lexer.peek().is_some_and(|t| t.is_op(right_paren)) {
+                    lexer.pop();
+                    let span = start..lexer.span().end;
+                    return Err(ParseError::EmptyGroup { span });
+                }
+                let (left, spans) = parse_bp(lexer, ctx, 0)?;
+                match lexer.pop() {
+                    Some(Token::Op(op)) if op == right_paren => {}
+                    Some(found) if found.is_right_paren() => {
+                        let expected = right_paren.to_string();
+                        let span = lexer.span();
+                        return Err(ParseError::MismatchedParen {
+                            expected,
+                            found,
+                            span,
+                        });
+                    }
+                    Some(found) => {
+                        let expected = Expected::Token(right_paren.to_string());
+                        let span = lexer.span();
+                        return Err(ParseError::UnexpectedToken {
+                            expected,
+                            found,
+                            span,
+                        });
+                    }
+                    None => ctx.unclosed(&token.to_string(), start, lexer.span().end)?,
+                }
+                let span = start..lexer.span().end;
+                // a bar can only be closed at where an operator is expected,
+                // so "||x|-1|" is "(abs (- (abs x) 1))".
+                if right_paren == "|" {
+                    ctx.allow.check_op(&Token::Symbol("abs".to_string()))?;
+                    ctx.stats.nodes += 1;
+                    let left = Expr::Cons(Token::Symbol("abs".to_string()), vec![left]);
+                    let children = vec![spans];
+                    (left, SpanTree { span, children })
+                } else {
+                    // parens are covered by the grouped node
+                    let children = spans.children;
+                    (left, SpanTree { span, children })
+                }
             } else {
-                let new_rbp = token
-                    .nud()
-                    .unwrap_or_else(|| panic!("unexpected prefix op: {:?}", token));
-                let right = parse_bp(lexer, new_rbp);
-                Expr::Cons(token, vec![right])
+                let new_rbp = ctx.nud(&token).ok_or_else(|| ParseError::UnexpectedToken {
+                    expected: Expected::Operand,
+                    found: token.clone(),
+                    span: lexer.span(),
+                })?;
+                let (right, right_spans) = parse_bp(lexer, ctx, new_rbp)?;
+                let span = start..right_spans.span.end;
+                let children = vec![right_spans];
+                ctx.stats.nodes += 1;
+                (Expr::Cons(token, vec![right]), SpanTree { span, children })
             }
         }
     };
 
+    // the right operand of the last comparison, and whether left is a chain
+    // of comparisons
+    let mut chain: Option<(Expr, SpanTree)> = None;
+    let mut chained = false;
     while let Some(token) = lexer.peek() {
-        if token.is_right_paren() {
+        if token.is_right_paren() || token.is_op(",") {
+            break;
+        }
+        if ctx.where_clause && matches!(token, Token::Symbol(s) if s == "where") {
             break;
         }
 
-        let token = token.clone();
-        let (lbp, new_rbp) = token
-            .led()
-            .unwrap_or_else(|| panic!("unexpected token: {:?}", token));
+        // adjacent groups are multiplied, there is no operator to pop out
+        let implicit = lexer.is_adjacent_group();
+        let token = if implicit {
+            Token::Op("*".to_string())
+        } else {
+            token.clone()
+        };
+        // a postfix operator only takes left, it's an Op even if it's a word
+        // so that it isn't taken for a function call
+        if let Some(lbp) = ctx.postfix(&token) {
+            if lbp < rbp {
+                break;
+            }
+            ctx.allow.check_op(&token)?;
+            lexer.pop();
+            ctx.stats.nodes += 1;
+            left = Expr::Cons(Token::Op(token.to_string()), vec![left]);
+            spans = SpanTree {
+                span: spans.span.start..lexer.span().end,
+                children: vec![spans],
+            };
+            chain = None;
+            chained = false;
+            continue;
+        }
+        let (lbp, new_rbp) = ctx.led(&token).ok_or_else(|| ParseError::UnexpectedToken {
+            expected: Expected::Operator,
+            found: token.clone(),
+            span: lexer.peek_span().unwrap_or_default(),
+        })?;
         if lbp < rbp {
             break;
         }
-        lexer.pop(); // pop out operator
-        let right = parse_bp(lexer, new_rbp);
-        left = Expr::Cons(token, vec![left, right])
+        ctx.allow.check_op(&token)?;
+        if !implicit {
+            lexer.pop(); // pop out operator
+        }
+        let (right, right_spans) = parse_bp(lexer, ctx, new_rbp)?;
+        ctx.stats.nodes += 1;
+        let comparison = matches!(&token, Token::Op(op) if COMPARISONS.contains(&op.as_str()));
+        match chain.take() {
+            // "1 < x < 10" is "(&& (< 1 x) (< x 10))", eval of "&&" evaluates
+            // the shared x once. A group isn't chained, "(1 < x) == true"
+            // compares the result of "<".
+            Some((shared, shared_spans)) if comparison => {
+                // the shared operand is in the tree twice
+                ctx.stats.nodes += node_count(&shared);
+                chain = Some((right.clone(), right_spans.clone()));
+                let span = shared_spans.span.start..right_spans.span.end;
+                let children = vec![shared_spans, right_spans];
+                let next = Expr::Cons(token, vec![shared, right]);
+                if !chained {
+                    ctx.allow.check_op(&Token::Op("&&".to_string()))?;
+                    ctx.stats.nodes += 1;
+                    left = Expr::Cons(Token::Op("&&".to_string()), vec![left]);
+                    spans = SpanTree {
+                        span: spans.span.clone(),
+                        children: vec![spans],
+                    };
+                    chained = true;
+                }
+                if let Expr::Cons(_, rest) = &mut left {
+                    rest.push(next);
+                }
+                spans.span.end = span.end;
+                spans.children.push(SpanTree { span, children });
+            }
+            _ => {
+                if comparison {
+                    chain = Some((right.clone(), right_spans.clone()));
+                }
+                chained = false;
+                left = Expr::Cons(token, vec![left, right]);
+                spans = SpanTree {
+                    span: spans.span.start..right_spans.span.end,
+                    children: vec![spans, right_spans],
+                };
+            }
+        }
+    }
+    ctx.depth -= 1;
+    Ok((left, spans))
+}
+
+// parse_call parse comma separated arguments of a function call like
+// "max(1, 2)", the name is already popped out
+fn parse_call(
+    lexer: &mut Lexer,
+    ctx: &mut Context,
+    name: Token,
+    start: usize,
+) -> Result<(Expr, SpanTree), ParseError> {
+    lexer.pop(); // pop out "("
+    let mut args = vec![];
+    let mut children = vec![];
+    if lexer.peek().is_some_and(|t| t.is_op(")")) {
+        lexer.pop();
+    } else {
+        loop {
+            let (arg, spans) = parse_bp(lexer, ctx, 0)?;
+            args.push(arg);
+            children.push(spans);
+            match lexer.pop() {
+                Some(Token::Op(op)) if op == "," => {}
+                Some(Token::Op(op)) if op == ")" => break,
+                Some(found) => {
+                    let expected = ")".to_string();
+                    let span = lexer.span();
+                    return Err(ParseError::MismatchedParen {
+                        expected,
+                        found,
+                        span,
+                    });
+                }
+                None => {
+                    ctx.unclosed("(", start, lexer.span().end)?;
+                    break;
+                }
+            }
+        }
+    }
+    let span = start..lexer.span().end;
+    ctx.stats.nodes += 1;
+    Ok((Expr::Cons(name, args), SpanTree { span, children }))
+}
+
+// Parser cache Exprs parsed from the same input, the least recently used one
+// is dropped when more than capacity inputs are cached. Errors aren't cached.
+pub struct Parser {
+    options: LexOptions,
+    capacity: usize,
+    cache: HashMap<String, Expr>,
+    // cached inputs from the least to the most recently used
+    order: VecDeque<String>,
+}
+
+impl Parser {
+    pub fn new(capacity: usize) -> Parser {
+        Parser::with_options(capacity, LexOptions::default())
+    }
+
+    pub fn with_options(capacity: usize, options: LexOptions) -> Parser {
+        Parser {
+            options,
+            capacity,
+            cache: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn parse(&mut self, input: &str) -> Result<Expr, ParseError> {
+        if let Some(expr) = self.cache.get(input) {
+            let expr = expr.clone();
+            self.touch(input);
+            return Ok(expr);
+        }
+        let expr = parse_with(input, &self.options)?;
+        if self.capacity > 0 {
+            if self.cache.len() >= self.capacity {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.cache.remove(&oldest);
+                }
+            }
+            self.cache.insert(input.to_string(), expr.clone());
+            self.order.push_back(input.to_string());
+        }
+        Ok(expr)
+    }
+
+    // move input to the most recently used end
+    fn touch(&mut self, input: &str) {
+        if let Some(i) = self.order.iter().position(|s| s == input) {
+            if let Some(s) = self.order.remove(i) {
+                self.order.push_back(s);
+            }
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // len return the number of cached inputs
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.cache.clear();
+        self.order.clear();
     }
-    left
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::flatten;
 
     #[test]
     fn test_infix() {
-        let s = parse("1 + 2 * 3");
+        let s = parse("1 + 2 * 3").unwrap();
         assert_eq!(s.to_string(), "(+ 1 (* 2 3))");
 
-        let s = parse("1 * 2 + 3");
+        let s = parse("1 * 2 + 3").unwrap();
         assert_eq!(s.to_string(), "(+ (* 1 2) 3)");
     }
 
+    #[test]
+    fn test_unicode_operator() {
+        let s = parse("2 × 3").unwrap();
+        assert_eq!(s.to_string(), "(* 2 3)");
+
+        let s = parse("2 × 3 ÷ 4").unwrap();
+        assert_eq!(s, parse("2 * 3 / 4").unwrap());
+
+        let s = parse("−1 − 2").unwrap();
+        assert_eq!(s.to_string(), "(- (- 1) 2)");
+    }
+
+    #[test]
+    fn test_prefix_only() {
+        let s = parse("√9 * 2").unwrap();
+        assert_eq!(s.to_string(), "(* (√ 9) 2)");
+
+        let s = parse("2 * √(x + 1) - √√16").unwrap();
+        assert_eq!(s.to_string(), "(- (* 2 (√ (+ x 1))) (√ (√ 16)))");
+
+        assert_eq!(
+            parse("9 √ 2"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Operator,
+                found: Token::Op("√".to_string()),
+                span: 2..5
+            })
+        );
+    }
+
+    #[test]
+    fn test_associativity() {
+        let s = parse("8 / 4 / 2").unwrap();
+        assert_eq!(s.to_string(), "(/ (/ 8 4) 2)");
+
+        let s = parse("10 - 3 - 2").unwrap();
+        assert_eq!(s.to_string(), "(- (- 10 3) 2)");
+
+        let s = parse("1 + 2 - 3 + 4").unwrap();
+        assert_eq!(s.to_string(), "(+ (- (+ 1 2) 3) 4)");
+
+        let s = parse("2 * 3 / 4 ^ 5 ^ 6").unwrap();
+        assert_eq!(s.to_string(), "(/ (* 2 3) (^ 4 (^ 5 6)))");
+    }
+
     #[test]
     fn test_prefix() {
-        let s = parse("-1*2 + 3");
+        let s = parse("-1*2 + 3").unwrap();
         assert_eq!(s.to_string(), "(+ (* (- 1) 2) 3)");
+
+        // a prefix "-" binds tighter than "*" and "/", whether its operand
+        // is a group or not
+        let s = parse("-(a+b)").unwrap();
+        assert_eq!(s.to_string(), "(- (+ a b))");
+
+        let s = parse("-(a+b)*c").unwrap();
+        assert_eq!(s.to_string(), "(* (- (+ a b)) c)");
+
+        let s = parse("-a*b").unwrap();
+        assert_eq!(s.to_string(), "(* (- a) b)");
+
+        let s = parse("--a/b").unwrap();
+        assert_eq!(s.to_string(), "(/ (- (- a)) b)");
     }
 
     #[test]
     fn test_group() {
-        let s = parse("(-1+2) * 3");
+        let s = parse("(-1+2) * 3").unwrap();
         assert_eq!(s.to_string(), "(* (+ (- 1) 2) 3)");
 
-        let s = parse("( -1 + 2 ) * 3 - -4");
+        let s = parse("( -1 + 2 ) * 3 - -4").unwrap();
         assert_eq!(s.to_string(), "(- (* (+ (- 1) 2) 3) (- 4))");
     }
+
+    #[test]
+    fn test_bracket() {
+        let s = parse("[1 + 2] * 3").unwrap();
+        assert_eq!(s.to_string(), "(* (+ 1 2) 3)");
+
+        let s = parse("[(1 + 2) * 3] - 4").unwrap();
+        assert_eq!(s.to_string(), "(- (* (+ 1 2) 3) 4)");
+
+        assert_eq!(
+            parse("(1]"),
+            Err(ParseError::MismatchedParen {
+                expected: ")".to_string(),
+                found: Token::Op("]".to_string()),
+                span: 2..3
+            })
+        );
+        assert_eq!(
+            parse("[1 + (2])"),
+            Err(ParseError::MismatchedParen {
+                expected: ")".to_string(),
+                found: Token::Op("]".to_string()),
+                span: 7..8
+            })
+        );
+    }
+
+    #[test]
+    fn test_abs() {
+        let s = parse("|x|").unwrap();
+        assert_eq!(s.to_string(), "(abs x)");
+
+        let s = parse("|x + 1| * 2").unwrap();
+        assert_eq!(s.to_string(), "(* (abs (+ x 1)) 2)");
+
+        let s = parse("|-3|").unwrap();
+        assert_eq!(s.to_string(), "(abs (- 3))");
+
+        let s = parse("||x|-1|").unwrap();
+        assert_eq!(s.to_string(), "(abs (- (abs x) 1))");
+
+        assert_eq!(parse("|x + 1"), Err(ParseError::UnmatchedParen));
+        assert_eq!(
+            parse("(x|"),
+            Err(ParseError::MismatchedParen {
+                expected: ")".to_string(),
+                found: Token::Op("|".to_string()),
+                span: 2..3
+            })
+        );
+    }
+
+    #[test]
+    fn test_adjacent_group() {
+        let s = parse("(1+2)(3+4)").unwrap();
+        assert_eq!(s.to_string(), "(* (+ 1 2) (+ 3 4))");
+
+        let s = parse("(1)(2)").unwrap();
+        assert_eq!(s.to_string(), "(* 1 2)");
+
+        // associate just like an explicit "*"
+        let s = parse("(1)(2)(3)").unwrap();
+        assert_eq!(s.to_string(), "(* (* 1 2) 3)");
+
+        let s = parse("1 + (2)[3] ^ 2").unwrap();
+        assert_eq!(s.to_string(), "(+ 1 (* 2 (^ 3 2)))");
+
+        // "2x" is a single symbol, it's not multiplied
+        assert_eq!(parse("2x"), Ok(Expr::Atom(Token::Symbol("2x".to_string()))));
+        assert_eq!(
+            parse("2 x"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Operator,
+                found: Token::Symbol("x".to_string()),
+                span: 2..3
+            })
+        );
+        assert_eq!(
+            parse("2(3)"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Operator,
+                found: Token::Op("(".to_string()),
+                span: 1..2
+            })
+        );
+    }
+
+    #[test]
+    fn test_call() {
+        let s = parse("max(1, 2 * 3) + 4").unwrap();
+        assert_eq!(s.to_string(), "(+ (max 1 (* 2 3)) 4)");
+
+        let s = parse("f(g(x), (1, 2").err();
+        assert_eq!(
+            s,
+            Some(ParseError::UnexpectedToken {
+                expected: Expected::Token(")".to_string()),
+                found: Token::Op(",".to_string()),
+                span: 10..11
+            })
+        );
+
+        let s = parse("sin(x)(2)").unwrap();
+        assert_eq!(s.to_string(), "(* (sin x) 2)");
+
+        assert_eq!(parse("f(1, 2"), Err(ParseError::UnmatchedParen));
+        assert_eq!(
+            parse("f(1]"),
+            Err(ParseError::MismatchedParen {
+                expected: ")".to_string(),
+                found: Token::Op("]".to_string()),
+                span: 3..4
+            })
+        );
+        assert_eq!(
+            parse("1, 2"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::End,
+                found: Token::Op(",".to_string()),
+                span: 1..2
+            })
+        );
+    }
+
+    #[test]
+    fn test_spanned() {
+        let (expr, spans) = parse_spanned("12 + 345").unwrap();
+        assert_eq!(spans.span, 0..8);
+        let node = node_at(&expr, &spans, 5).unwrap();
+        assert_eq!(node, &Expr::Atom(Token::Symbol("345".to_string())));
+        assert_eq!(node_at(&expr, &spans, 3), Some(&expr));
+        assert_eq!(node_at(&expr, &spans, 8), None);
+
+        let (expr, spans) = parse_spanned("-(1 + 2)*3").unwrap();
+        assert_eq!(spans.children[0].span, 0..8);
+        assert_eq!(spans.children[0].children[0].span, 1..8);
+        let node = node_at(&expr, &spans, 1).unwrap();
+        assert_eq!(node.to_string(), "(+ 1 2)");
+    }
+
+    #[test]
+    fn test_to_tokens() {
+        let join = |tokens: Vec<Token>| {
+            tokens
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        for input in [
+            "1 + 2 * 3",
+            "(1 + 2) * 3",
+            "-(1 + 2) ^ 2",
+            "2 ^ -3 ^ 2",
+            "(2 ^ 3) ^ 2",
+            "max(1, -2) * f() - g(x + 1)",
+            "|1 - 2| * -[3 - 4]",
+            "1 - (2 - 3)",
+            "(1 - 2) - 3",
+            "0 <= x < y != z",
+            "(1 < x) == (y > 2) && 3 < 4 < z",
+        ] {
+            let s = parse(input).unwrap();
+            assert_eq!(parse(&join(s.to_tokens())), Ok(s), "{}", input);
+        }
+
+        // an n-ary node is parsed back as the binary ones it's flattened from
+        let s = flatten(parse("1 + (2 + 3) * 4 + (x + y)").unwrap());
+        assert_eq!(join(s.to_tokens()), "1 + ( 2 + 3 ) * 4 + x + y");
+        assert_eq!(flatten(parse(&join(s.to_tokens())).unwrap()), s);
+        let s = flatten(parse("a * b * -c").unwrap());
+        assert_eq!(join(s.to_tokens()), "a * b * - c");
+
+        let s = parse("(1 + 2) * 3").unwrap();
+        assert_eq!(join(s.to_tokens()), "( 1 + 2 ) * 3");
+        let s = parse("((1)) + (2 * 3)").unwrap();
+        assert_eq!(join(s.to_tokens()), "1 + 2 * 3");
+    }
+
+    #[test]
+    fn test_debug_truncated() {
+        let s = parse("1 + 2 * (3 - (4 + 5))").unwrap();
+        assert_eq!(s.debug_truncated(2), "(+ 1 (* …))");
+        assert_eq!(s.debug_truncated(3), "(+ 1 (* 2 (- …)))");
+        assert_eq!(s.debug_truncated(1), "(+ …)");
+        assert_eq!(s.debug_truncated(0), "…");
+        // deep enough to print everything
+        assert_eq!(s.debug_truncated(5), s.to_string());
+        assert_eq!(s.debug_truncated(100), s.to_string());
+        assert_eq!(parse("f()").unwrap().debug_truncated(1), "(f)");
+    }
+
+    #[test]
+    fn test_display_as() {
+        let s = parse("(1 + 2) * 3").unwrap();
+        assert_eq!(
+            s.display_as(DisplayStyle::Sexpr).to_string(),
+            "(* (+ 1 2) 3)"
+        );
+        assert_eq!(s.display_as(DisplayStyle::Sexpr).to_string(), s.to_string());
+        assert_eq!(s.display_as(DisplayStyle::Infix).to_string(), "(1 + 2) * 3");
+
+        for input in [
+            "-(1 + 2) ^ 2",
+            "2 ^ -3 ^ 2",
+            "max(1, -2) * f() - g(x + 1)",
+            "|1 - 2| * -|3 - 4|",
+            "1 - (2 - 3)",
+            "√9 + √(x + 1)",
+            "0 <= x < y != z",
+            "(1 < x) == true",
+        ] {
+            let s = parse(input).unwrap();
+            assert_eq!(s.display_as(DisplayStyle::Infix).to_string(), input);
+        }
+        let s = flatten(parse("1 + 2 + (3 + x) * 2").unwrap());
+        assert_eq!(
+            s.display_as(DisplayStyle::Infix).to_string(),
+            "1 + 2 + (3 + x) * 2"
+        );
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let input: &[u8] = b"1 + 2\n\n  \n3 * (4\r\n-5)\n2 +\n7\n";
+        let results = parse_lines(input)
+            .map(|s| s.map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Ok("(+ 1 2)".to_string()),
+                Ok("(* 3 (- 4 5))".to_string()),
+                Err(ParseError::UnexpectedEof),
+                Ok("7".to_string()),
+            ]
+        );
+
+        // open groups go on to the next line, a paren in a comment or a
+        // string doesn't
+        let input: &[u8] = b"max(\n1, # (\n2)\n[1 +\n2] * 3\n\"(\" # (\n(1\n";
+        let results = parse_lines(input)
+            .map(|s| s.map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Ok("(max 1 2)".to_string()),
+                Ok("(* (+ 1 2) 3)".to_string()),
+                Ok("\"(\"".to_string()),
+                Err(ParseError::UnmatchedParen),
+            ]
+        );
+
+        // a bad line doesn't stop the following ones
+        let input: &[u8] = b"1\n\xff\n2\n";
+        let results = parse_lines(input)
+            .map(|s| s.map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Ok("1".to_string()),
+                Err(ParseError::Io(io::ErrorKind::InvalidData)),
+                Ok("2".to_string()),
+            ]
+        );
+
+        let input: &[u8] = b"1 + \\\n  2 * \\\r\n3\n4\n";
+        let results = parse_lines(input)
+            .map(|s| s.map(|s| s.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [Ok("(+ 1 (* 2 3))".to_string()), Ok("4".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_multi_line() {
+        let s = parse("1 + \\\n2 - \\\n3").unwrap();
+        assert_eq!(s.to_string(), "(- (+ 1 2) 3)");
+        // a newline is only a space, inside parens or not
+        let s = parse("max(\n  1,\n  (2 +\n   3)\n) *\n4").unwrap();
+        assert_eq!(s.to_string(), "(* (max 1 (+ 2 3)) 4)");
+        // a "\" not ending a line is still a token
+        assert!(parse("1 \\ 2").is_err());
+        // nothing follows a continuation at the end of input
+        assert_eq!(parse("1 + \\"), Err(ParseError::UnexpectedEof));
+        assert_eq!(parse("1 + \\ \n"), Err(ParseError::UnexpectedEof));
+        let input: &[u8] = b"1 + \\\n";
+        let results = parse_lines(input).collect::<Vec<_>>();
+        assert_eq!(results, [Err(ParseError::UnexpectedEof)]);
+    }
+
+    #[test]
+    fn test_parser_cache() {
+        let mut parser = Parser::new(2);
+        let s = parser.parse("1 + 2").unwrap();
+        assert_eq!(parser.parse("1 + 2"), Ok(s));
+        assert_eq!(parser.len(), 1);
+
+        parser.parse("3 * 4").unwrap();
+        parser.parse("1 + 2").unwrap();
+        // "3 * 4" is the least recently used one
+        parser.parse("5 - 6").unwrap();
+        assert_eq!(parser.len(), 2);
+        assert!(parser.cache.contains_key("1 + 2"));
+        assert!(!parser.cache.contains_key("3 * 4"));
+
+        assert!(parser.parse("1 +").is_err());
+        assert_eq!(parser.len(), 2);
+
+        parser.clear();
+        assert!(parser.is_empty());
+        assert_eq!(parser.capacity(), 2);
+
+        let mut parser = Parser::new(0);
+        parser.parse("1").unwrap();
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn test_restricted() {
+        let allow = AllowList::default().deny("/");
+        assert_eq!(
+            parse_restricted("1 / 2", &allow),
+            Err(ParseError::DisallowedOperator("/".to_string()))
+        );
+        let s = parse_restricted("1 + 2", &allow).unwrap();
+        assert_eq!(s.to_string(), "(+ 1 2)");
+
+        // both the prefix and infix "-" are denied
+        let allow = AllowList::default().deny("-");
+        assert!(parse_restricted("-1", &allow).is_err());
+        assert!(parse_restricted("2 - 1", &allow).is_err());
+
+        let allow = AllowList::default().deny("sqrt");
+        assert!(parse_restricted("sqrt(4)", &allow).is_err());
+        assert!(parse_restricted("max(1, 4)", &allow).is_ok());
+
+        let allow = AllowList::default().deny_calls();
+        assert_eq!(
+            parse_restricted("1 + max(1, 4)", &allow),
+            Err(ParseError::DisallowedOperator("max".to_string()))
+        );
+        assert!(parse_restricted("x * (y + 1)", &allow).is_ok());
+
+        let allow = AllowList::default().deny("deg");
+        for input in ["90deg", "x deg", "1 + 2 deg * 3"] {
+            assert_eq!(
+                parse_restricted(input, &allow),
+                Err(ParseError::DisallowedOperator("deg".to_string()))
+            );
+        }
+        assert!(parse_restricted("90rad", &allow).is_ok());
+
+        // aliases are denied with the operators they stand for
+        let denied = |op: &str, input| parse_restricted(input, &AllowList::default().deny(op));
+        let disallowed = |op: &str| Err(ParseError::DisallowedOperator(op.to_string()));
+        assert_eq!(denied("^", "2 ** 3"), disallowed("^"));
+        assert_eq!(denied("**", "2 ^ 3"), disallowed("^"));
+        assert_eq!(denied("×", "2 × 3"), disallowed("*"));
+        assert_eq!(denied("×", "2 * 3"), disallowed("*"));
+        assert_eq!(denied("÷", "2 / 3"), disallowed("/"));
+        assert_eq!(denied("−", "−3"), disallowed("-"));
+        // so are the nodes the parser makes up
+        assert_eq!(denied("abs", "1 + |x|"), disallowed("abs"));
+        assert_eq!(denied("abs", "abs(x)"), disallowed("abs"));
+        assert_eq!(denied("&&", "1 < 2 < 3"), disallowed("&&"));
+        assert_eq!(denied("&&", "1 < 2 && 2 < 3"), disallowed("&&"));
+        assert!(denied("&&", "1 < 2").is_ok());
+    }
+
+    #[test]
+    fn test_string() {
+        let s = parse(r#""hello" + "world""#).unwrap();
+        assert_eq!(s.to_string(), r#"(+ "hello" "world")"#);
+        let s = parse(r#"concat("a b", "c \" d")"#).unwrap();
+        assert_eq!(s.to_string(), r#"(concat "a b" "c \" d")"#);
+        assert_eq!(
+            parse(r#""hello"#),
+            Err(ParseError::Lex(LexError::UnterminatedString))
+        );
+    }
+
+    #[test]
+    fn test_postfix() {
+        let s = parse("sin(90deg)").unwrap();
+        assert_eq!(s.to_string(), "(sin (deg 90))");
+        let s = parse("-90deg + x rad * 2").unwrap();
+        assert_eq!(s.to_string(), "(+ (- (deg 90)) (* (rad x) 2))");
+        let s = parse("2 ^ (1 + 2)deg").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 (deg (+ 1 2)))");
+        // only digits are split from the operator
+        let s = parse("1e2deg").unwrap();
+        assert_eq!(s, Expr::Atom(Token::Symbol("1e2deg".to_string())));
+        assert!(parse("deg").is_ok());
+
+        for input in ["sin(90 deg)", "(1 + 2) deg ^ 2", "-x deg"] {
+            let s = parse(input).unwrap();
+            assert_eq!(s.display_as(DisplayStyle::Infix).to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_comparison_chain() {
+        let s = parse("1 < x < 10").unwrap();
+        assert_eq!(s.to_string(), "(&& (< 1 x) (< x 10))");
+        let s = parse("0 <= x + 1 < y * 2 != z").unwrap();
+        assert_eq!(
+            s.to_string(),
+            "(&& (<= 0 (+ x 1)) (< (+ x 1) (* y 2)) (!= (* y 2) z))"
+        );
+        // a group or a looser operator ends the chain
+        let s = parse("(1 < x) == true").unwrap();
+        assert_eq!(s.to_string(), "(== (< 1 x) true)");
+        let s = parse("a < b && b < c").unwrap();
+        assert_eq!(s.to_string(), "(&& (< a b) (< b c))");
+        let s = parse("a < b && c < d < e").unwrap();
+        assert_eq!(s.to_string(), "(&& (< a b) (&& (< c d) (< d e)))");
+
+        let (s, spans) = parse_spanned("1 < x < 10").unwrap();
+        let x = node_at(&s, &spans, 4).unwrap();
+        assert_eq!(x.to_string(), "x");
+        assert_eq!(spans.children[1].span, 4..10);
+    }
+
+    #[test]
+    fn test_parse_with_stats() {
+        let (s, stats) = parse_with_stats("1 + 2 * 3");
+        assert_eq!(s.unwrap().to_string(), "(+ 1 (* 2 3))");
+        assert_eq!(
+            stats,
+            ParseStats {
+                tokens: 5,
+                max_depth: 3,
+                nodes: 5,
+            }
+        );
+
+        let (_, stats) = parse_with_stats("max(|x|, (((1))))");
+        assert_eq!(stats.tokens, 14);
+        assert_eq!(stats.max_depth, 5);
+        assert_eq!(stats.nodes, 4);
+
+        // the shared "x" and "x + 1" are counted twice like in the tree
+        let (s, stats) = parse_with_stats("1 < x < 3");
+        assert_eq!(stats.nodes, node_count(&s.unwrap()));
+        assert_eq!(stats.nodes, 7);
+        let (s, stats) = parse_with_stats("1 < x + 1 < 3 < 4");
+        assert_eq!(stats.nodes, node_count(&s.unwrap()));
+
+        let (s, stats) = parse_with_stats("1 + * 2");
+        assert!(s.is_err());
+        assert_eq!(stats.tokens, 4);
+        assert_eq!(stats.nodes, 1);
+    }
+
+    #[test]
+    fn test_parse_until() {
+        let (s, rest) = parse_until("1 * 2 + 3", 15).unwrap();
+        assert_eq!(s.to_string(), "(* 1 2)");
+        assert_eq!(
+            rest,
+            [Token::Op("+".to_string()), Token::Symbol("3".to_string())]
+        );
+
+        let (s, rest) = parse_until("1 * 2 + 3", 0).unwrap();
+        assert_eq!(s.to_string(), "(+ (* 1 2) 3)");
+        assert!(rest.is_empty());
+
+        // groups are parsed as a whole, prefix operators still bind tighter
+        let (s, rest) = parse_until("-(1 + 2) ^ 2 * 3 - 4", 30).unwrap();
+        assert_eq!(s.to_string(), "(- (^ (+ 1 2) 2))");
+        assert_eq!(rest.len(), 4);
+    }
+
+    #[test]
+    fn test_lenient() {
+        assert_eq!(parse("(1 + 2"), Err(ParseError::UnmatchedParen));
+        let (s, diagnostics) = parse_lenient("(1 + 2").unwrap();
+        assert_eq!(s.to_string(), "(+ 1 2)");
+        assert_eq!(
+            diagnostics,
+            [Diagnostic {
+                message: "unclosed '(' closed at end of input".to_string(),
+                span: 0..6,
+            }]
+        );
+
+        let (s, diagnostics) = parse_lenient("max(1, |x - [2").unwrap();
+        assert_eq!(s.to_string(), "(max 1 (abs (- x 2)))");
+        let spans = diagnostics.into_iter().map(|d| d.span).collect::<Vec<_>>();
+        assert_eq!(spans, [12..14, 7..14, 0..14]);
+
+        // only a missing right paren is tolerated
+        let (_, diagnostics) = parse_lenient("(1 + 2)").unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(parse_lenient("(1 +"), Err(ParseError::UnexpectedEof));
+        assert!(parse_lenient("(1 + 2]").is_err());
+    }
+
+    #[test]
+    fn test_parse_where() {
+        let s = parse_where("x + y where x = 1, y = max(2, x)").unwrap();
+        assert_eq!(s.expr.to_string(), "(+ x y)");
+        let bindings = s
+            .bindings
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            [("x", "1".to_string()), ("y", "(max 2 x)".to_string())]
+        );
+        assert!(parse_where("x * 2").unwrap().bindings.is_empty());
+
+        assert_eq!(parse_where("x where"), Err(ParseError::UnexpectedEof));
+        assert_eq!(
+            parse_where("x where 1 = 2"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Name,
+                found: Token::Symbol("1".to_string()),
+                span: 8..9
+            })
+        );
+        for (input, keyword, span) in [
+            ("x where true = 1", "true", 8..12),
+            ("x where false = 1", "false", 8..13),
+            ("where where where = 1", "where", 12..17),
+        ] {
+            assert_eq!(
+                parse_where(input),
+                Err(ParseError::UnexpectedToken {
+                    expected: Expected::Name,
+                    found: Token::Symbol(keyword.to_string()),
+                    span
+                })
+            );
+        }
+        assert_eq!(
+            parse_where("x where x == 2"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Token("=".to_string()),
+                found: Token::Op("==".to_string()),
+                span: 10..12
+            })
+        );
+        assert_eq!(
+            parse_where("x where x = 1 y = 2"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::Operator,
+                found: Token::Symbol("y".to_string()),
+                span: 14..15
+            })
+        );
+        let err = parse_where("x where x = 1, y = 2, x = 3").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::DuplicateBinding {
+                name: "x".to_string(),
+                span: 22..23
+            }
+        );
+        assert_eq!(err.to_string(), "variable 'x' is bound more than once");
+        // only the first "where" starts bindings
+        assert!(parse_where("x where x = 1 where y = 2").is_err());
+        assert!(parse("x where x = 1").is_err());
+    }
+
+    #[test]
+    fn test_longest_valid_prefix() {
+        let prefix = |input| {
+            let (s, len) = longest_valid_prefix(input).unwrap();
+            (s.to_string(), len)
+        };
+        assert_eq!(prefix("1 + 2 +"), ("(+ 1 2)".to_string(), 5));
+        assert_eq!(prefix("1 * (2 + 3"), ("1".to_string(), 1));
+        assert_eq!(prefix("f(x) + 1"), ("(+ (f x) 1)".to_string(), 8));
+        assert_eq!(prefix("-2 ) + 1"), ("(- 2)".to_string(), 2));
+        // half typed strings and comments
+        assert_eq!(prefix("1 + 2 + \"ab"), ("(+ 1 2)".to_string(), 5));
+        assert_eq!(prefix("1 + 2 /* x"), ("(+ 1 2)".to_string(), 5));
+        assert_eq!(prefix("\"a\" + \"b\\\"c"), ("\"a\"".to_string(), 3));
+        assert_eq!(longest_valid_prefix("\"ab"), None);
+        assert_eq!(longest_valid_prefix("* 2"), None);
+        assert_eq!(longest_valid_prefix(""), None);
+    }
+
+    #[test]
+    fn test_error() {
+        assert_eq!(parse("1 +"), Err(ParseError::UnexpectedEof));
+        assert_eq!(parse("(1 + 2"), Err(ParseError::UnmatchedParen));
+        assert_eq!(
+            parse("1 + 2)"),
+            Err(ParseError::UnexpectedToken {
+                expected: Expected::End,
+                found: Token::Op(")".to_string()),
+                span: 5..6
+            })
+        );
+        assert_eq!(
+            parse("1 /* 2"),
+            Err(ParseError::Lex(LexError::UnterminatedComment))
+        );
+
+        assert_eq!(parse("()"), Err(ParseError::EmptyGroup { span: 0..2 }));
+        assert_eq!(parse("1 + [ ]"), Err(ParseError::EmptyGroup { span: 4..7 }));
+        // a call can have no argument
+        assert_eq!(parse("f() * 2").unwrap().to_string(), "(* (f) 2)");
+        assert!(parse("f(())").is_err());
+
+        let err = parse("1 + )").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::UnexpectedToken {
+                expected: Expected::Operand,
+                found: Token::Op(")".to_string()),
+                span: 4..5
+            }
+        );
+        assert_eq!(err.to_string(), "expected an operand but found ')'");
+
+        let err = parse("1 2").unwrap_err();
+        assert_eq!(
+            err,
+            ParseError::UnexpectedToken {
+                expected: Expected::Operator,
+                found: Token::Symbol("2".to_string()),
+                span: 2..3
+            }
+        );
+        assert_eq!(err.to_string(), "expected an operator but found '2'");
+    }
+
+    #[test]
+    fn test_max_tokens() {
+        let options = LexOptions {
+            max_tokens: Some(3),
+            ..Default::default()
+        };
+        let s = parse_with("1 + 2", &options).unwrap();
+        assert_eq!(s.to_string(), "(+ 1 2)");
+        assert_eq!(
+            parse_with("1 + 2 * 3", &options),
+            Err(ParseError::Lex(LexError::TooManyTokens))
+        );
+    }
+
+    #[test]
+    fn test_trivia() {
+        let options = LexOptions {
+            preserve_trivia: true,
+            ..Default::default()
+        };
+        let s = parse_with(" 1 +  (2) # two", &options).unwrap();
+        assert_eq!(s.to_string(), "(+ 1 2)");
+    }
+
+    #[test]
+    fn test_comment() {
+        let s = parse("1 + /* 2 + */ 3 # * 4").unwrap();
+        assert_eq!(s.to_string(), "(+ 1 3)");
+    }
+
+    #[test]
+    fn test_power() {
+        let s = parse("2 ^ -3").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 (- 3))");
+
+        let s = parse("-2 ^ 2").unwrap();
+        assert_eq!(s.to_string(), "(- (^ 2 2))");
+
+        let s = parse("2 ^ -3 ^ 2").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 (- (^ 3 2)))");
+
+        let s = parse("2 ^ 3 ^ 2").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 (^ 3 2))");
+    }
+
+    #[test]
+    fn test_double_star() {
+        let s = parse("2 ** 3").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 3)");
+
+        let s = parse("2 ** 3 ** 2").unwrap();
+        assert_eq!(s.to_string(), "(^ 2 (^ 3 2))");
+
+        let s = parse("2 * 3").unwrap();
+        assert_eq!(s.to_string(), "(* 2 3)");
+
+        let s = parse("2*3**2*4").unwrap();
+        assert_eq!(s.to_string(), "(* (* 2 (^ 3 2)) 4)");
+    }
+
+    #[test]
+    fn test_supported_operators() {
+        let ops = supported_operators();
+        let entry = |symbol, fixity, precedence, associativity| OperatorInfo {
+            symbol,
+            fixity,
+            precedence,
+            associativity,
+        };
+        for op in [
+            entry("-", Fixity::Prefix, 30, Associativity::Right),
+            entry("√", Fixity::Prefix, 30, Associativity::Right),
+            entry("??", Fixity::Infix, 1, Associativity::Right),
+            entry("&&", Fixity::Infix, 3, Associativity::Left),
+            entry("<=", Fixity::Infix, 5, Associativity::Left),
+            entry("-", Fixity::Infix, 10, Associativity::Left),
+            entry("^", Fixity::Infix, 40, Associativity::Right),
+            entry("deg", Fixity::Postfix, 50, Associativity::Left),
+        ] {
+            assert!(ops.contains(&op), "{:?}", op);
+        }
+        // aliases aren't operators of their own
+        assert!(!ops.iter().any(|op| op.symbol == "**" || op.symbol == "×"));
+
+        let precedence = |symbol| {
+            ops.iter()
+                .find(|op| op.symbol == symbol && op.fixity == Fixity::Infix)
+                .map(|op| op.precedence)
+        };
+        assert_eq!(precedence("+"), Some(10));
+        assert_eq!(precedence("-"), Some(10));
+        assert_eq!(precedence("*"), Some(20));
+        assert_eq!(precedence("/"), Some(20));
+        assert!(ops
+            .iter()
+            .any(|op| op.symbol == "-" && op.fixity == Fixity::Prefix));
+
+        let associativity = |symbol| {
+            ops.iter()
+                .find(|op| op.symbol == symbol && op.fixity == Fixity::Infix)
+                .map(|op| op.associativity)
+        };
+        assert_eq!(associativity("-"), Some(Associativity::Left));
+        assert_eq!(associativity("/"), Some(Associativity::Left));
+        assert_eq!(associativity("^"), Some(Associativity::Right));
+    }
 }
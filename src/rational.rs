@@ -0,0 +1,91 @@
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, Zero};
+
+use crate::{
+    eval::EvalError,
+    lexer::{is_number, Token},
+    parser::Expr,
+};
+
+pub type Rational = BigRational;
+
+// eval_rational is eval but compute with exact rationals, a decimal literal
+// like "0.1" is exactly 1/10 instead of the nearest f64.
+pub fn eval_rational(expr: &Expr) -> Result<Rational, EvalError> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) => parse_decimal(s),
+        Expr::Cons(Token::Symbol(name), args) => match (name.as_str(), args.as_slice()) {
+            ("abs", [x]) => Ok(eval_rational(x)?.abs()),
+            _ => Err(EvalError::UnknownFunction(name.clone())),
+        },
+        Expr::Cons(Token::Op(op), args) => {
+            let args = args
+                .iter()
+                .map(eval_rational)
+                .collect::<Result<Vec<_>, _>>()?;
+            match (op.as_str(), args.as_slice()) {
+                ("+", [a]) => Ok(a.clone()),
+                ("-", [a]) => Ok(-a),
+                ("+", [a, b]) => Ok(a + b),
+                ("-", [a, b]) => Ok(a - b),
+                ("*", [a, b]) => Ok(a * b),
+                ("/", [_, b]) if b.is_zero() => Err(EvalError::DivisionByZero),
+                ("/", [a, b]) => Ok(a / b),
+                _ => Err(EvalError::UnknownOperator(op.clone())),
+            }
+        }
+        Expr::Atom(token) | Expr::Cons(token, _) => {
+            Err(EvalError::UnknownOperator(token.to_string()))
+        }
+    }
+}
+
+// parse_decimal read digits with an optional fraction like "12.05" as
+// 1205/100
+fn parse_decimal(s: &str) -> Result<Rational, EvalError> {
+    if !is_number(s) {
+        return Err(EvalError::UndefinedVariable(s.to_string()));
+    }
+    let invalid = || EvalError::InvalidNumber(s.to_string());
+    let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+    if whole.len() + fraction.len() == 0
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+    let numer = format!("{}{}", whole, fraction)
+        .parse::<BigInt>()
+        .map_err(|_| invalid())?;
+    let denom = BigInt::from(10).pow(fraction.len() as u32);
+    Ok(Rational::new(numer, denom))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn eval_str(input: &str) -> Result<Rational, EvalError> {
+        eval_rational(&parse(input).unwrap())
+    }
+
+    fn ratio(numer: i64, denom: i64) -> Rational {
+        Rational::new(numer.into(), denom.into())
+    }
+
+    #[test]
+    fn test_eval_rational() {
+        assert_eq!(eval_str("0.1 + 0.2"), Ok(ratio(3, 10)));
+        assert_eq!(eval_str("1/3 * 3"), Ok(ratio(1, 1)));
+        assert_eq!(eval_str("-|1.5 - 2|"), Ok(ratio(-1, 2)));
+        assert_eq!(eval_str("1 / (0.5 - .5)"), Err(EvalError::DivisionByZero));
+        assert_eq!(
+            eval_str("1.2.3"),
+            Err(EvalError::InvalidNumber("1.2.3".to_string()))
+        );
+    }
+}
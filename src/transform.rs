@@ -0,0 +1,564 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+};
+
+use crate::{lexer::Token, parser::Expr};
+
+impl Expr {
+    // structural_hash hash the whole tree, structurally equal trees always
+    // have the same hash so they can be grouped by it.
+    pub fn structural_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // get_path return the node reached by following child indexes from the
+    // root, [0, 1] is the second child of the first child. An empty path is
+    // the root itself.
+    pub fn get_path(&self, path: &[usize]) -> Option<&Expr> {
+        path.iter().try_fold(self, |node, &i| match node {
+            Expr::Cons(_, rest) => rest.get(i),
+            Expr::Atom(_) => None,
+        })
+    }
+}
+
+// Cse is an expression where every repeated subtree is computed once into a
+// binding, bindings only refer to the ones before them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cse {
+    pub bindings: Vec<(String, Expr)>,
+    pub expr: Expr,
+}
+
+// cse, common subexpression elimination, replace a subtree appearing more
+// than once by a variable bound to it, "(x+1) * (x+1)" becomes "_t0 * _t0"
+// with "_t0 = x+1". A name already in the expression is skipped.
+pub fn cse(expr: &Expr) -> Cse {
+    let mut counts = HashMap::new();
+    count_subtrees(expr, &mut counts);
+    let mut taken = HashSet::new();
+    collect_symbols(expr, &mut taken);
+    let mut names = HashMap::new();
+    let mut bindings = vec![];
+    let expr = share_subtrees(expr, &counts, &taken, &mut names, &mut bindings);
+    Cse { bindings, expr }
+}
+
+// collect_symbols gather the variable and function names of the expression
+fn collect_symbols<'a>(expr: &'a Expr, symbols: &mut HashSet<&'a str>) {
+    match expr {
+        Expr::Atom(Token::Symbol(name)) => {
+            symbols.insert(name);
+        }
+        Expr::Atom(_) => {}
+        Expr::Cons(head, rest) => {
+            if let Token::Symbol(name) = head {
+                symbols.insert(name);
+            }
+            for child in rest {
+                collect_symbols(child, symbols);
+            }
+        }
+    }
+}
+
+fn count_subtrees<'a>(expr: &'a Expr, counts: &mut HashMap<&'a Expr, usize>) {
+    if let Expr::Cons(_, rest) = expr {
+        *counts.entry(expr).or_default() += 1;
+        for child in rest {
+            count_subtrees(child, counts);
+        }
+    }
+}
+
+fn share_subtrees<'a>(
+    expr: &'a Expr,
+    counts: &HashMap<&'a Expr, usize>,
+    taken: &HashSet<&str>,
+    names: &mut HashMap<&'a Expr, String>,
+    bindings: &mut Vec<(String, Expr)>,
+) -> Expr {
+    let Expr::Cons(head, rest) = expr else {
+        return expr.clone();
+    };
+    if let Some(name) = names.get(expr) {
+        return Expr::Atom(Token::Symbol(name.clone()));
+    }
+    let rest = rest
+        .iter()
+        .map(|child| share_subtrees(child, counts, taken, names, bindings))
+        .collect();
+    let shared = Expr::Cons(head.clone(), rest);
+    if counts.get(expr).copied().unwrap_or(0) < 2 {
+        return shared;
+    }
+    let name = (bindings.len()..)
+        .map(|i| format!("_t{}", i))
+        .find(|name| {
+            !taken.contains(name.as_str()) && bindings.iter().all(|(bound, _)| bound != name)
+        })
+        .unwrap();
+    names.insert(expr, name.clone());
+    bindings.push((name.clone(), shared));
+    Expr::Atom(Token::Symbol(name))
+}
+
+// ASSOCIATIVE are operators chains of which can be merged into one node
+pub(crate) const ASSOCIATIVE: &[&str] = &["+", "*", "&&"];
+
+// flatten merge chains of the same associative operator into one node with
+// all the operands, "1 + 2 + 3 + 4" becomes "(+ 1 2 3 4)". Other operators
+// like "-" are left alone.
+pub fn flatten(expr: Expr) -> Expr {
+    let Expr::Cons(head, rest) = expr else {
+        return expr;
+    };
+    let rest = rest.into_iter().map(flatten);
+    let associative = matches!(&head, Token::Op(op) if ASSOCIATIVE.contains(&op.as_str()));
+    // a prefix "+" isn't a part of the chain
+    if !associative || rest.len() < 2 {
+        return Expr::Cons(head, rest.collect());
+    }
+    let mut operands = vec![];
+    for child in rest {
+        match child {
+            Expr::Cons(op, children) if op == head && children.len() >= 2 => {
+                operands.extend(children)
+            }
+            child => operands.push(child),
+        }
+    }
+    Expr::Cons(head, operands)
+}
+
+// COMMUTATIVE are operators whose operands can be in any order. "&&" isn't
+// one, it stops at the first false operand so "false && x" is false but
+// "x && false" fails on x.
+const COMMUTATIVE: &[&str] = &["+", "*", "==", "!="];
+
+// is_commutative check if the operands of op can be swapped, canonical_hash
+// sorts the operands of these operators
+pub fn is_commutative(op: &str) -> bool {
+    COMMUTATIVE.contains(&op)
+}
+
+// canonical_hash hash the tree normalized by flatten, with operands of
+// commutative operators sorted, so "1+2+3" and "3+(2+1)" hash the same. The
+// hash is FNV-1a of to_bytes, it doesn't change across runs, machines or
+// versions of Rust.
+pub fn canonical_hash(expr: &Expr) -> u64 {
+    let bytes = normalize(flatten(expr.clone())).to_bytes();
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn normalize(expr: Expr) -> Expr {
+    let Expr::Cons(head, rest) = expr else {
+        return expr;
+    };
+    let mut rest = rest.into_iter().map(normalize).collect::<Vec<_>>();
+    if matches!(&head, Token::Op(op) if is_commutative(op)) {
+        rest.sort_by_cached_key(Expr::to_bytes);
+    }
+    Expr::Cons(head, rest)
+}
+
+// MAX_PARENTHESIZED is the most operands parenthesizations splits, there are
+// already 429 ways for 8 operands
+pub const MAX_PARENTHESIZED: usize = 8;
+
+// parenthesizations list every binary tree of an n-ary associative node like
+// the "(+ 1 2 3)" of flatten, keeping the order of operands, "(+ (+ 1 2) 3)"
+// and "(+ 1 (+ 2 3))". There are Catalan(n - 1) of them for n operands. Any
+// other node, or one with more than MAX_PARENTHESIZED operands, is returned
+// alone.
+pub fn parenthesizations(expr: &Expr) -> Vec<Expr> {
+    match expr {
+        Expr::Cons(head @ Token::Op(op), rest)
+            if ASSOCIATIVE.contains(&op.as_str())
+                && rest.len() > 2
+                && rest.len() <= MAX_PARENTHESIZED =>
+        {
+            binary_trees(head, rest)
+        }
+        _ => vec![expr.clone()],
+    }
+}
+
+fn binary_trees(head: &Token, operands: &[Expr]) -> Vec<Expr> {
+    if operands.len() == 1 {
+        return vec![operands[0].clone()];
+    }
+    let mut trees = vec![];
+    for at in 1..operands.len() {
+        let lefts = binary_trees(head, &operands[..at]);
+        let rights = binary_trees(head, &operands[at..]);
+        for left in &lefts {
+            for right in &rights {
+                trees.push(Expr::Cons(head.clone(), vec![left.clone(), right.clone()]));
+            }
+        }
+    }
+    trees
+}
+
+// rename_var rename every variable from to to, a number or a function of
+// the same name like the "x" of "x(x)" isn't renamed
+pub fn rename_var(expr: Expr, from: &str, to: &str) -> Expr {
+    match expr {
+        Expr::Atom(token @ Token::Symbol(_)) if token.is_name() && token.to_string() == from => {
+            Expr::Atom(Token::Symbol(to.to_string()))
+        }
+        Expr::Atom(_) => expr,
+        Expr::Cons(head, rest) => Expr::Cons(
+            head,
+            rest.into_iter()
+                .map(|child| rename_var(child, from, to))
+                .collect(),
+        ),
+    }
+}
+
+// contains check if needle is structurally equal to any subtree of haystack,
+// including haystack itself
+pub fn contains(haystack: &Expr, needle: &Expr) -> bool {
+    haystack == needle
+        || matches!(haystack, Expr::Cons(_, rest) if rest.iter().any(|child| contains(child, needle)))
+}
+
+// max_arity return the largest number of children of any node, 0 for an atom
+pub fn max_arity(expr: &Expr) -> usize {
+    match expr {
+        Expr::Atom(_) => 0,
+        Expr::Cons(_, rest) => rest.iter().map(max_arity).fold(rest.len(), usize::max),
+    }
+}
+
+// over_arity find every node of a binary operator having more than two
+// operands, which only a hand-built tree can have. Chains of associative
+// operators merged by flatten are fine.
+pub fn over_arity(expr: &Expr) -> Vec<&Expr> {
+    let mut found = vec![];
+    find_over_arity(expr, &mut found);
+    found
+}
+
+fn find_over_arity<'a>(expr: &'a Expr, found: &mut Vec<&'a Expr>) {
+    if let Expr::Cons(head, rest) = expr {
+        let associative = matches!(head, Token::Op(op) if ASSOCIATIVE.contains(&op.as_str()));
+        if rest.len() > 2 && head.led().is_some() && !associative {
+            found.push(expr);
+        }
+        for child in rest {
+            find_over_arity(child, found);
+        }
+    }
+}
+
+// Change is a difference between two trees, path addresses the node like in
+// get_path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    // a child appended after the children of before
+    Added {
+        path: Vec<usize>,
+        expr: Expr,
+    },
+    // a child of before past the children of after
+    Removed {
+        path: Vec<usize>,
+        expr: Expr,
+    },
+    Replaced {
+        path: Vec<usize>,
+        before: Expr,
+        after: Expr,
+    },
+}
+
+// diff compare two trees node by node, nodes with the same head have their
+// children compared pairwise, any other differing node is replaced as a
+// whole. Children are never matched across positions.
+pub fn diff(before: &Expr, after: &Expr) -> Vec<Change> {
+    let mut changes = vec![];
+    diff_at(before, after, &mut vec![], &mut changes);
+    changes
+}
+
+fn diff_at(before: &Expr, after: &Expr, path: &mut Vec<usize>, changes: &mut Vec<Change>) {
+    match (before, after) {
+        _ if before == after => {}
+        (Expr::Cons(head, old), Expr::Cons(new_head, new)) if head == new_head => {
+            for i in 0..old.len().max(new.len()) {
+                path.push(i);
+                match (old.get(i), new.get(i)) {
+                    (Some(old), Some(new)) => diff_at(old, new, path, changes),
+                    (Some(old), None) => changes.push(Change::Removed {
+                        path: path.clone(),
+                        expr: old.clone(),
+                    }),
+                    (None, Some(new)) => changes.push(Change::Added {
+                        path: path.clone(),
+                        expr: new.clone(),
+                    }),
+                    (None, None) => unreachable!(),
+                }
+                path.pop();
+            }
+        }
+        _ => changes.push(Change::Replaced {
+            path: path.clone(),
+            before: before.clone(),
+            after: after.clone(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    // count operators and function calls to evaluate
+    fn count_ops(expr: &Expr) -> usize {
+        match expr {
+            Expr::Atom(_) => 0,
+            Expr::Cons(_, rest) => 1 + rest.iter().map(count_ops).sum::<usize>(),
+        }
+    }
+
+    #[test]
+    fn test_structural_hash() {
+        let s = parse("(x+1) * (x+1)").unwrap();
+        let Expr::Cons(_, rest) = &s else {
+            panic!("not a cons: {}", s)
+        };
+        assert_eq!(rest[0].structural_hash(), rest[1].structural_hash());
+        assert_eq!(
+            s.structural_hash(),
+            parse("(x + 1)*[x + 1]").unwrap().structural_hash()
+        );
+        assert_ne!(
+            rest[0].structural_hash(),
+            parse("x+2").unwrap().structural_hash()
+        );
+    }
+
+    #[test]
+    fn test_canonical_hash() {
+        let hash = |input| canonical_hash(&parse(input).unwrap());
+        assert_eq!(hash("1+2+3"), hash("3+2+1"));
+        assert_eq!(hash("1+2+3"), hash("3 + (2 + 1)"));
+        assert_eq!(hash("x*y == 2 && z"), hash("2 == y*x && z"));
+        assert_ne!(hash("false && x"), hash("x && false"));
+        assert_ne!(hash("1-2"), hash("2-1"));
+        assert_ne!(hash("1+2+3"), hash("1+2*3"));
+        // fixed across runs
+        assert_eq!(hash("1+2"), 3582007745089643252);
+    }
+
+    #[test]
+    fn test_is_commutative() {
+        for op in ["+", "*", "=="] {
+            assert!(is_commutative(op), "{}", op);
+        }
+        for op in ["-", "/", "^", "<", "&&", "max"] {
+            assert!(!is_commutative(op), "{}", op);
+        }
+    }
+
+    #[test]
+    fn test_get_path() {
+        let s = parse("1 + 2 * 3").unwrap();
+        assert_eq!(s.get_path(&[]), Some(&s));
+        assert_eq!(
+            s.get_path(&[1, 0]).map(Expr::to_string),
+            Some("2".to_string())
+        );
+        assert_eq!(
+            s.get_path(&[1, 1]).map(Expr::to_string),
+            Some("3".to_string())
+        );
+        assert_eq!(
+            s.get_path(&[1]).map(Expr::to_string),
+            Some("(* 2 3)".to_string())
+        );
+
+        // out of range, or going through an atom
+        assert_eq!(s.get_path(&[2]), None);
+        assert_eq!(s.get_path(&[0, 0]), None);
+        assert_eq!(s.get_path(&[1, 1, 0]), None);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let s = flatten(parse("1 + 2 + 3 + 4").unwrap());
+        assert_eq!(s.to_string(), "(+ 1 2 3 4)");
+
+        let s = flatten(parse("1 * (2 * 3) * 4 + 5 * 6 * 7").unwrap());
+        assert_eq!(s.to_string(), "(+ (* 1 2 3 4) (* 5 6 7))");
+
+        let s = flatten(parse("1 - 2 - 3").unwrap());
+        assert_eq!(s.to_string(), "(- (- 1 2) 3)");
+
+        let s = flatten(parse("+(+1) + 2 + -(3 + 4)").unwrap());
+        assert_eq!(s.to_string(), "(+ (+ (+ 1)) 2 (- (+ 3 4)))");
+    }
+
+    #[test]
+    fn test_parenthesizations() {
+        let s = flatten(parse("1 + 2 + 3").unwrap());
+        let trees = parenthesizations(&s);
+        let trees = trees.iter().map(Expr::to_string).collect::<Vec<_>>();
+        assert_eq!(trees, ["(+ 1 (+ 2 3))", "(+ (+ 1 2) 3)"]);
+
+        // Catalan numbers
+        let s = flatten(parse("a * b * c * d * e").unwrap());
+        assert_eq!(parenthesizations(&s).len(), 14);
+        let s = flatten(parse("1 + 2 + 3 + 4 + 5 + 6 + 7 + 8").unwrap());
+        assert_eq!(parenthesizations(&s).len(), 429);
+
+        // too many operands, or not a chain
+        let s = flatten(parse("1 + 2 + 3 + 4 + 5 + 6 + 7 + 8 + 9").unwrap());
+        assert_eq!(parenthesizations(&s), [s]);
+        let s = parse("1 - 2").unwrap();
+        assert_eq!(parenthesizations(&s), [s]);
+    }
+
+    #[test]
+    fn test_rename_var() {
+        let rename = |input, from, to| rename_var(parse(input).unwrap(), from, to).to_string();
+        assert_eq!(rename("x + x * y", "x", "z"), "(+ z (* z y))");
+        assert_eq!(rename("x(x) - |x|", "x", "t"), "(- (x t) (abs t))");
+        assert_eq!(rename("x + 1", "1", "y"), "(+ x 1)");
+        // nothing to rename
+        let s = parse("a + b * 2").unwrap();
+        assert_eq!(rename_var(s.clone(), "x", "z"), s);
+    }
+
+    #[test]
+    fn test_contains() {
+        let s = parse("(x+1) * y").unwrap();
+        assert!(contains(&s, &parse("x+1").unwrap()));
+        assert!(contains(&s, &parse("y").unwrap()));
+        assert!(contains(&s, &s));
+        assert!(!contains(&s, &parse("x+2").unwrap()));
+        assert!(!contains(&s, &parse("1 * y").unwrap()));
+        assert!(!contains(&parse("x").unwrap(), &s));
+    }
+
+    #[test]
+    fn test_max_arity() {
+        let s = parse("1 + 2 * -3").unwrap();
+        assert_eq!(max_arity(&s), 2);
+        assert!(over_arity(&s).is_empty());
+        assert_eq!(max_arity(&parse("x").unwrap()), 0);
+
+        let s = flatten(parse("1 + 2 + max(1, 2) + 4").unwrap());
+        assert_eq!(max_arity(&s), 4);
+        assert!(over_arity(&s).is_empty());
+
+        let atom = |s: &str| Expr::Atom(Token::Symbol(s.to_string()));
+        let bad = Expr::Cons(
+            Token::Op("-".to_string()),
+            vec![atom("1"), atom("2"), atom("3")],
+        );
+        let s = Expr::Cons(Token::Op("*".to_string()), vec![bad.clone(), atom("4")]);
+        assert_eq!(max_arity(&s), 3);
+        assert_eq!(over_arity(&s), [&bad]);
+    }
+
+    #[test]
+    fn test_diff() {
+        let diff_str = |before, after| diff(&parse(before).unwrap(), &parse(after).unwrap());
+        assert_eq!(diff_str("1 + 2 * x", "1 + 2*x"), []);
+        assert_eq!(
+            diff_str("1 + 2", "3"),
+            [Change::Replaced {
+                path: vec![],
+                before: parse("1 + 2").unwrap(),
+                after: parse("3").unwrap(),
+            }]
+        );
+        assert_eq!(
+            diff_str("1 + 2 * x", "1 + 2 * y"),
+            [Change::Replaced {
+                path: vec![1, 1],
+                before: parse("x").unwrap(),
+                after: parse("y").unwrap(),
+            }]
+        );
+        // a different operator replace the node with its children
+        assert_eq!(
+            diff_str("(a - b) * c", "(a + b) * c"),
+            [Change::Replaced {
+                path: vec![0],
+                before: parse("a - b").unwrap(),
+                after: parse("a + b").unwrap(),
+            }]
+        );
+        assert_eq!(
+            diff_str("max(a, b)", "max(a, c, d)"),
+            [
+                Change::Replaced {
+                    path: vec![1],
+                    before: parse("b").unwrap(),
+                    after: parse("c").unwrap(),
+                },
+                Change::Added {
+                    path: vec![2],
+                    expr: parse("d").unwrap(),
+                },
+            ]
+        );
+        assert_eq!(
+            diff_str("max(a, b)", "max(a)"),
+            [Change::Removed {
+                path: vec![1],
+                expr: parse("b").unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_cse() {
+        let s = parse("(x+1) * (x+1)").unwrap();
+        let shared = cse(&s);
+        assert_eq!(shared.bindings.len(), 1);
+        assert_eq!(shared.bindings[0].0, "_t0");
+        assert_eq!(shared.bindings[0].1.to_string(), "(+ x 1)");
+        assert_eq!(shared.expr.to_string(), "(* _t0 _t0)");
+        let ops = count_ops(&shared.expr)
+            + shared
+                .bindings
+                .iter()
+                .map(|(_, e)| count_ops(e))
+                .sum::<usize>();
+        assert_eq!(count_ops(&s), 3);
+        assert_eq!(ops, 2);
+
+        let s = parse("sqrt(x+1) - sqrt(x+1) * (x+1)").unwrap();
+        let shared = cse(&s);
+        assert_eq!(shared.bindings[0].1.to_string(), "(+ x 1)");
+        assert_eq!(shared.bindings[1].1.to_string(), "(sqrt _t0)");
+        assert_eq!(shared.expr.to_string(), "(- _t1 (* _t1 _t0))");
+
+        // names in the expression aren't reused
+        let s = parse("(x+1)*(x+1) + _t0 + (y*2)*(y*2) + _t2").unwrap();
+        let shared = cse(&s);
+        assert_eq!(shared.bindings[0].0, "_t1");
+        assert_eq!(shared.bindings[1].0, "_t3");
+        assert_eq!(
+            shared.expr.to_string(),
+            "(+ (+ (+ (* _t1 _t1) _t0) (* _t3 _t3)) _t2)"
+        );
+
+        let s = parse("x + y").unwrap();
+        assert_eq!(cse(&s).bindings, []);
+        assert_eq!(cse(&s).expr, s);
+    }
+}
@@ -0,0 +1,216 @@
+use std::fmt;
+
+use crate::{
+    eval::{call, eval, parse_number, EvalError},
+    lexer::{is_number, split_number, Token},
+    parser::Expr,
+};
+
+// Dimension is the exponent of every base unit in a quantity, a speed is
+// length 1 and time -1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dimension {
+    pub length: i32,
+    pub time: i32,
+}
+
+impl Dimension {
+    pub const NONE: Dimension = Dimension { length: 0, time: 0 };
+    pub const LENGTH: Dimension = Dimension { length: 1, time: 0 };
+    pub const TIME: Dimension = Dimension { length: 0, time: 1 };
+}
+
+// Quantity is a value in base units, meter for length and second for time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub dimension: Dimension,
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)?;
+        for (unit, exp) in [("m", self.dimension.length), ("s", self.dimension.time)] {
+            match exp {
+                0 => {}
+                1 => write!(f, " {}", unit)?,
+                _ => write!(f, " {}^{}", unit, exp)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// UNITS are the unit suffixes a number can have, with their scale to the base
+// unit
+const UNITS: &[(&str, f64, Dimension)] = &[
+    ("mm", 0.001, Dimension::LENGTH),
+    ("cm", 0.01, Dimension::LENGTH),
+    ("m", 1.0, Dimension::LENGTH),
+    ("km", 1000.0, Dimension::LENGTH),
+    ("ms", 0.001, Dimension::TIME),
+    ("s", 1.0, Dimension::TIME),
+    ("min", 60.0, Dimension::TIME),
+    ("h", 3600.0, Dimension::TIME),
+];
+
+// eval_units is eval but numbers can have a unit suffix like "3km" or "5s",
+// units of the same dimension are converted to each other and mixing
+// different dimensions in "+" or "-" is an error.
+pub fn eval_units(expr: &Expr) -> Result<Quantity, EvalError> {
+    match expr {
+        Expr::Atom(Token::Symbol(s)) if is_number(s) => parse_quantity(s),
+        Expr::Cons(Token::Op(op), args) => {
+            let args = args.iter().map(eval_units).collect::<Result<Vec<_>, _>>()?;
+            apply(op, &args)
+        }
+        // "abs" keeps the unit, other functions only take plain numbers
+        Expr::Cons(Token::Symbol(name), args) => {
+            let args = args.iter().map(eval_units).collect::<Result<Vec<_>, _>>()?;
+            match args.as_slice() {
+                [x] if name == "abs" => Ok(Quantity {
+                    value: x.value.abs(),
+                    dimension: x.dimension,
+                }),
+                _ if args.iter().any(|arg| arg.dimension != Dimension::NONE) => {
+                    Err(EvalError::IncompatibleUnits(name.clone()))
+                }
+                _ => {
+                    let values = args.iter().map(|arg| arg.value).collect::<Vec<_>>();
+                    Ok(Quantity {
+                        value: call(name, &values)?,
+                        dimension: Dimension::NONE,
+                    })
+                }
+            }
+        }
+        // anything else, like a constant, has no unit
+        _ => Ok(Quantity {
+            value: eval(expr)?,
+            dimension: Dimension::NONE,
+        }),
+    }
+}
+
+// parse_quantity split a literal like "3.5km" into its number and unit
+fn parse_quantity(s: &str) -> Result<Quantity, EvalError> {
+    // a number with an exponent like "1e3" or an SI prefix like "3k" has no
+    // unit, "3km" is still kilometers.
+    if let Some(value) = parse_number(s) {
+        return Ok(Quantity {
+            value,
+            dimension: Dimension::NONE,
+        });
+    }
+    let (number, unit) = split_number(s);
+    let value = number
+        .parse::<f64>()
+        .map_err(|_| EvalError::InvalidNumber(s.to_string()))?;
+    match UNITS.iter().find(|(name, _, _)| *name == unit) {
+        Some((_, scale, dimension)) => Ok(Quantity {
+            value: value * scale,
+            dimension: *dimension,
+        }),
+        None => Err(EvalError::UnknownUnit(unit.to_string())),
+    }
+}
+
+fn apply(op: &str, args: &[Quantity]) -> Result<Quantity, EvalError> {
+    let incompatible = || EvalError::IncompatibleUnits(op.to_string());
+    let quantity = |value, dimension| Ok(Quantity { value, dimension });
+    match (op, args) {
+        ("+", [a]) => Ok(*a),
+        ("-", [a]) => quantity(-a.value, a.dimension),
+        ("+" | "-", [a, b]) if a.dimension != b.dimension => Err(incompatible()),
+        ("+", [a, b]) => quantity(a.value + b.value, a.dimension),
+        ("-", [a, b]) => quantity(a.value - b.value, a.dimension),
+        ("*", [a, b]) => quantity(
+            a.value * b.value,
+            Dimension {
+                length: a.dimension.length + b.dimension.length,
+                time: a.dimension.time + b.dimension.time,
+            },
+        ),
+        ("/", [_, b]) if b.value == 0.0 => Err(EvalError::DivisionByZero),
+        ("/", [a, b]) => quantity(
+            a.value / b.value,
+            Dimension {
+                length: a.dimension.length - b.dimension.length,
+                time: a.dimension.time - b.dimension.time,
+            },
+        ),
+        // only a unitless number can be raised, to a unitless power
+        ("^", [a, b]) if a.dimension != Dimension::NONE || b.dimension != Dimension::NONE => {
+            Err(incompatible())
+        }
+        ("^", [a, b]) => quantity(a.value.powf(b.value), Dimension::NONE),
+        _ => Err(EvalError::UnknownOperator(op.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn eval_str(input: &str) -> Result<Quantity, EvalError> {
+        eval_units(&parse(input).unwrap())
+    }
+
+    #[test]
+    fn test_eval_units() {
+        let q = eval_str("3km + 500m").unwrap();
+        assert_eq!(q.value, 3500.0);
+        assert_eq!(q.dimension, Dimension::LENGTH);
+        assert_eq!(q.to_string(), "3500 m");
+
+        let q = eval_str("(1.5km - 500m) / 2min").unwrap();
+        assert_eq!(q.to_string(), "8.333333333333334 m s^-1");
+
+        let q = eval_str("1e3 * 1m").unwrap();
+        assert_eq!(q, eval_str("1km").unwrap());
+        let q = eval_str("2 * 3s + 2 ^ 2 * 1s").unwrap();
+        assert_eq!(q.to_string(), "10 s");
+
+        // "k" is a kilo prefix of the unit, or an SI prefix of the number
+        assert_eq!(eval_str("3km").unwrap().to_string(), "3000 m");
+        assert_eq!(eval_str("3k * 1m").unwrap().to_string(), "3000 m");
+
+        // an exponent is a part of the number
+        assert_eq!(eval_str("1.5e3m").unwrap().to_string(), "1500 m");
+        assert_eq!(eval_str("2e3mm + 1E1m").unwrap().to_string(), "12 m");
+        // functions
+        assert_eq!(eval_str("|1km - 3km|").unwrap().to_string(), "2000 m");
+        assert_eq!(eval_str("abs(-2s)").unwrap().to_string(), "2 s");
+        assert_eq!(eval_str("max(1, 2) * 1m").unwrap().to_string(), "2 m");
+    }
+
+    #[test]
+    fn test_units_error() {
+        assert_eq!(
+            eval_str("3km + 5s"),
+            Err(EvalError::IncompatibleUnits("+".to_string()))
+        );
+        assert_eq!(
+            eval_str("3km - 5"),
+            Err(EvalError::IncompatibleUnits("-".to_string()))
+        );
+        assert_eq!(
+            eval_str("3kg"),
+            Err(EvalError::UnknownUnit("kg".to_string()))
+        );
+        // a plain 0 has no unit
+        assert_eq!(
+            eval_str("|0 - 3km|"),
+            Err(EvalError::IncompatibleUnits("-".to_string()))
+        );
+        assert_eq!(
+            eval_str("2em"),
+            Err(EvalError::UnknownUnit("em".to_string()))
+        );
+        assert_eq!(
+            eval_str("sqrt(4m)"),
+            Err(EvalError::IncompatibleUnits("sqrt".to_string()))
+        );
+    }
+}
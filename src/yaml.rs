@@ -0,0 +1,118 @@
+use crate::{
+    lexer::{is_number, Token},
+    parser::Expr,
+};
+
+impl Expr {
+    // to_yaml write the tree as a YAML document, an atom is a mapping of
+    // num, symbol or str to its value, a node is a mapping of its op or call
+    // and its args as a sequence. Names and operators are always quoted.
+    //   op: "+"
+    //   args:
+    //     - num: 1
+    //     - symbol: "x"
+    pub fn to_yaml(&self) -> String {
+        let mut yaml = String::new();
+        write_yaml(self, 0, &mut yaml);
+        yaml
+    }
+}
+
+// write_yaml write the mapping of expr, its first line is not indented as it
+// follows "- " for a child
+fn write_yaml(expr: &Expr, indent: usize, yaml: &mut String) {
+    match expr {
+        // written normalized, YAML 1.1 reads "1e5" and "1." as strings but
+        // "100000" and "1" as numbers. A literal too big for f64 is infinite.
+        Expr::Atom(Token::Symbol(s)) if is_number(s) => match s.parse::<f64>() {
+            Ok(value) if value.is_infinite() => yaml.push_str("num: .inf\n"),
+            Ok(value) => yaml.push_str(&format!("num: {}\n", value)),
+            // a literal like "3k" isn't a YAML number
+            Err(_) => yaml.push_str(&format!("num: {}\n", quote(s))),
+        },
+        Expr::Atom(Token::Str(s)) => yaml.push_str(&format!("str: {}\n", quote(s))),
+        Expr::Atom(token) => yaml.push_str(&format!("symbol: {}\n", quote(&token.to_string()))),
+        Expr::Cons(head, rest) => {
+            let key = if matches!(head, Token::Op(_)) {
+                "op"
+            } else {
+                "call"
+            };
+            let pad = " ".repeat(indent);
+            yaml.push_str(&format!(
+                "{}: {}\n{}args:",
+                key,
+                quote(&head.to_string()),
+                pad
+            ));
+            if rest.is_empty() {
+                yaml.push_str(" []\n");
+                return;
+            }
+            yaml.push('\n');
+            for child in rest {
+                yaml.push_str(&format!("{}  - ", pad));
+                write_yaml(child, indent + 4, yaml);
+            }
+        }
+    }
+}
+
+// quote write s as a double quoted YAML scalar
+fn quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    #[test]
+    fn test_to_yaml() {
+        let s = parse("1 + 2 * 3").unwrap();
+        assert_eq!(
+            s.to_yaml(),
+            "op: \"+\"\n\
+             args:\n  \
+               - num: 1\n  \
+               - op: \"*\"\n    \
+                 args:\n      \
+                   - num: 2\n      \
+                   - num: 3\n"
+        );
+
+        let s = parse(r#"f() - max(x, "a\"b", 3k)"#).unwrap();
+        assert_eq!(
+            s.to_yaml(),
+            "op: \"-\"\n\
+             args:\n  \
+               - call: \"f\"\n    \
+                 args: []\n  \
+               - call: \"max\"\n    \
+                 args:\n      \
+                   - symbol: \"x\"\n      \
+                   - str: \"a\\\"b\"\n      \
+                   - num: \"3k\"\n"
+        );
+
+        let yaml = |input| parse(input).unwrap().to_yaml();
+        assert_eq!(yaml("1e5"), "num: 100000\n");
+        assert_eq!(yaml("1."), "num: 1\n");
+        assert_eq!(yaml("007.50"), "num: 7.5\n");
+        assert_eq!(yaml(".5e3"), "num: 500\n");
+        assert_eq!(yaml("0.000025"), "num: 0.000025\n");
+        assert_eq!(yaml("1e999"), "num: .inf\n");
+    }
+}